@@ -1,17 +1,85 @@
 use ansi_term::{Colour, Style};
 use regex::bytes::Regex;
 use regex::bytes::RegexBuilder;
-use std::cmp::Ordering;
 use std::collections::HashMap;
 use std::collections::VecDeque;
+use std::io::BufRead;
+use std::io::IsTerminal;
+use std::io::Read;
+use std::io::Seek;
+use std::io::Write;
+use std::time::Duration;
 
 macro_rules! HELP_TEXT {() => (
 r###"
-Usage: {binary_name} [OPTION]... [COMMAND]...
-Reads text from stdin and processes it by applying the commands to every line.
+Usage: {binary_name} [OPTION]... [COMMAND]... [-- FILE...]
+Reads text from stdin (or from the FILEs, in order) and processes it by
+applying the commands to every line. Inputs compressed with gzip, xz or zstd
+are transparently decompressed.
 
 Options:
-  -h, --help    This usage help.
+  -h, --help            This usage help.
+  -f, --file FILE       Reads the input from FILE instead of stdin. Can be
+                        specified multiple times. Every argument after "--"
+                        is also considered an input FILE. A FILE named "-"
+                        means stdin.
+  -F, --follow FILE     Reads the input from FILE like -f, but keeps waiting
+                        for new data to be appended when reaching its end,
+                        like "tail -F" does. Truncated and rotated files are
+                        read again from the start. It must be the last FILE.
+  -H, --with-filename   Prints the FILE name before every output line.
+  -A, --after-context N Prints N lines of context after every selected line.
+  -B, --before-context N
+                        Prints N lines of context before every selected line.
+  -C, --context N       Prints N lines of context before and after every
+                        selected line. Context lines are dimmed, and groups of
+                        lines which aren't contiguous are separated by "--".
+  --time-format=FORMAT  Layout of the timestamps in the lines, used by the time
+                        filter: gst, syslog, logcat, iso8601 or auto (the
+                        default), which detects it in every input from the
+                        first line having a recognizable timestamp. ISO-8601
+                        times which aren't at the start of the lines are only
+                        detected after finding them in 3 lines.
+  -k, --keep-whitespace Keeps the leading and trailing whitespace of the lines
+                        (only the line break is removed). By default, lines
+                        are trimmed.
+  --strip-ansi          Removes the ANSI escape sequences (eg: colors) that the
+                        input lines may already have before applying the
+                        commands, so they don't prevent the regexes from
+                        matching nor get mixed with the highlighting.
+  --keep-ansi           Keeps the ANSI escape sequences of the input lines in
+                        the output, but applies the commands to a copy of the
+                        lines without them.
+  --color=WHEN          When to color the output: auto (the default), always or
+                        never. In auto mode, the output is only colored when
+                        it's a terminal and the NO_COLOR environment variable
+                        isn't set.
+  --stable-colors       Derives the color of each thread, category or object
+                        from a hash of its id, so it's always the same in every
+                        run, whatever the filters applied. Otherwise, they get
+                        colors in order of appearance.
+  --topology[=FORMAT]   Instead of printing the lines, reconstructs the pipeline
+                        topology from the pad (GST_ELEMENT_PADS) and link
+                        (GST_PADS) lines of a GStreamer log, and prints it at
+                        the end of each input as a Graphviz graph (FORMAT dot,
+                        the default) or as an indented list of elements, pads
+                        and links (FORMAT tree).
+  --topology-at=TIME    Prints the topology as it was at TIME, instead of at
+                        the end of the input.
+  --state-timeline[=FORMAT]
+                        Extracts the state changes of every element (eg: NULL
+                        to READY) from the GST_STATES lines of a GStreamer log,
+                        and prints them at the end of each input as a table
+                        with their times, durations and results (FORMAT table,
+                        the default) or as a chart of the state of every
+                        element along the time (FORMAT gantt). Only the lines
+                        in the selected time ranges are considered.
+  --theme=THEME         Colors used for the highlighting: dark (the default),
+                        light, high-contrast, colorblind or classic (every
+                        combination of the 16 basic colors). 24-bit colors are
+                        used when the COLORTERM environment variable is
+                        "truecolor" or "24bit", and the closest ones of the
+                        256-color palette otherwise.
 
 Commands:
   REGEX, fc:REGEX     Filters the line and only prints it if it contains text
@@ -26,18 +94,97 @@ Commands:
   s:/REGEX/REPLACE    Substitution. Replaces one pattern for another. Any other
                       delimiter character can be used instead of /, it that's
                       more convenient to the user.
-  ft:[TIME]-[TIME]    Time filter. Assuming the lines start with a TIME,
+  ft:[TIME]-[TIME]    Time filter. Assuming the lines have a TIME,
                       selects only the lines between the target start and end
                       TIME. Any of the TIME arguments (or both) can be omitted,
                       but the delimiter (-) must be present. Specifying multiple
                       time filters will generate matches that fit on any of the
                       time ranges. Overlapping ranges can trigger undefined
-                      behaviour.
-  ht:                 Highlight threads. Assuming a GStreamer log, where the
+                      behaviour. Lines without TIME (eg: the continuation of a
+                      multiline message) follow the selection of the previous
+                      line.
+  ft:+DURATION        Relative time filters. Select the first DURATION of the
+  ft:TIME+DURATION    input (counting from its first TIME), the DURATION
+  ft:-DURATION$       starting at TIME, or the last DURATION of the input
+                      (counting back from its last TIME), respectively. The
+                      last one can't be used when following a file.
+  dt:[DURATION][,first]
+                      Time deltas. Adds a column before every printed line with
+                      the time elapsed since the previous printed line (and
+                      since the first printed line, if "first" is specified).
+                      Deltas longer than DURATION are highlighted in red. The
+                      times are detected like in the time filter.
+  gap:DURATION        Gap detection. Prints a marker line before every line
+                      whose time is more than DURATION after the time of the
+                      previous line with time, and a summary of the biggest
+                      gaps at the end of each input. When that line isn't
+                      printed, the marker (with the biggest of those gaps)
+                      goes before the next printed line. The times are
+                      detected like in the time filter.
+  cat:RULE[,RULE]     GStreamer debug category filter. Assuming a GStreamer log,
+                      where the category appears as the fifth word in the
+                      line, selects only the lines whose category and level
+                      are selected by the RULEs, which use the same syntax as
+                      GST_DEBUG: CATEGORY[:LEVEL]. CATEGORY can use * and ?
+                      wildcards, and the lines with a LEVEL more severe or
+                      equal to the one specified (or any LEVEL, if omitted)
+                      are selected. A LEVEL of 0 or a "-" before CATEGORY
+                      exclude it. When several RULEs match a category, the
+                      last one applies.
+  hc:[ID=STYLE,...]   Highlight categories. Assuming a GStreamer log,
+                      highlights each debug category in a different color,
+                      or in the STYLE given to its ID (the category name).
+  ho:[ID=STYLE,...]   Highlight objects. Assuming a GStreamer log, highlights
+                      each element referenced in the line (eg: <qtdemux0> or
+                      <qtdemux0:audio_0>) in a different color (or in the
+                      STYLE given to its ID, the element name), and each of
+                      its pads in a shade of that color.
+  lvl:LEVEL           GStreamer debug level filter. Assuming a GStreamer log,
+  lvl:=LEVEL[,LEVEL]  where the level appears as the fourth word in the line,
+                      selects only the lines with LEVEL or a more severe one
+                      (eg: lvl:warning selects ERROR and WARNING lines), or
+                      only the lines with the listed LEVELs, when "=" is used.
+                      The LEVEL can be a name (error, warning, fixme, info,
+                      debug, log, trace, memdump) or its number.
+  pts:[DURATION]      Buffer timestamps check. Extracts the pts, dts and
+                      duration (or dur) values from the lines, and tracks them
+                      for every object (the name between <> after the function
+                      in GStreamer logs). Lines where the dts (or the pts, if
+                      there's no dts) goes backwards, or where the pts isn't
+                      where the previous pts plus duration predicts (a hole or
+                      an overlap longer than DURATION, 1ms by default), get
+                      those problems appended between [] and highlighted.
+  pc:[diff]           Pretty caps. Appends the GstCaps and GstStructures found
+                      in the line (eg: video/x-raw, width=(int)1920, ...) to it,
+                      indented and with a field in each line. With "diff",
+                      the changes since the previous caps of the same object
+                      (the name between <> after the function in GStreamer
+                      logs) are marked: added (+), removed (-) and changed
+                      (~, with the old and new values) fields and structures.
+  e:EXPRESSION        Boolean filter expression. Selects only the lines where
+                      the EXPRESSION is true. The EXPRESSION is made of REGEXes
+                      combined with the & (and), | (or) and ! (not) operators
+                      and grouped with parentheses. REGEXes containing spaces
+                      or operator characters must be "double quoted" (or have
+                      those characters escaped with \). Lines not matching the
+                      EXPRESSION are discarded, regardless of other filters.
+                      Like n:, it can only reject lines, so the lines where
+                      it's true must also pass the rest of filters, whatever
+                      their order. Every REGEX is highlighted in a different
+                      color.
+  ht:[ID=STYLE,...]   Highlight threads. Assuming a GStreamer log, where the
                       thread id appears as the third word in the line,
-                      highlights each thread in a different color.
+                      highlights each thread in a different color, or in the
+                      STYLE given to its ID (eg: ht:0x1ee2320=red,bold).
 
 The REGEX pattern is a regular expression. All the matches are case insensitive.
+The fc:, h: and e: commands can have a pinned STYLE instead of the next one of
+the theme, like in fc{{red,bold}}:error. The STYLE is a list of colors (a name,
+such as red, a number from 0 to 255 or #rrggbb), background colors (the same,
+prefixed by on_) and effects (bold, dimmed, italic, underline, reverse).
+
+The REGEXes always match the plain text of the line, never the highlighting added
+by previous commands. When the highlighted texts overlap, the last command wins.
 When used for substitutions, capture groups can be defined as
 (?CAPTURE_NAMEREGEX). See examples at the bottom.
 
@@ -45,11 +192,25 @@ The REPLACEment string is the text that the REGEX will be replaced by when doing
 substitutions. Text captured by a named capture group can be referred to by
 ${{CAPTURE_NAME}}. See examples at the bottom.
 
-The TIME pattern can be any sequence of numbers, colon (:) or dot (.).
-Typically, it will be a GStreamer timestamp (eg: 0:01:10.881123150), but it
-actually can be any other numerical sequence. Times are compared
-lexicographically, so it's important that all of them have the same string
-length.
+The fc:, fn:, n:, h: and s: commands can be restricted to a FIELD of GStreamer
+log lines by writing it after the command name, like in fc@object:REGEX or
+s@message:/REGEX/REPLACE. Lines which aren't GStreamer log lines don't match
+then. The FIELDs are timestamp, pid, thread, level, category, file, line,
+function, object (the name between <> after the function) and message. The
+fields are found even if the log has ANSI colors (GST_DEBUG_COLOR_MODE=on).
+
+The TIME pattern is a GStreamer timestamp (H:MM:SS.NNNNNNNNN, eg:
+0:01:10.881123150), a number of seconds (eg: 70.88) or a number with a unit
+(ns, us, ms, s, m or h, eg: 70881ms). The DURATION pattern is a number with a
+unit (eg: 200ms, 1.5s). Times are compared numerically, so they can be
+written with any precision: an end TIME includes every time that starts with it
+(eg: "-24.79" includes 24.7905).
+
+Wall clock times can also be used, as long as the lines use the same format:
+syslog/journald (eg: Oct 17 12:03:44), logcat (eg: 10-17 12:03:44.123) and
+ISO-8601 (eg: 2025-10-17T12:03:44.123Z, which can appear anywhere in the line,
+like in JSON logs). The seconds can be omitted in TIME to select whole minutes.
+Times with a UTC offset are converted to UTC.
 
 Examples:
 
@@ -71,6 +232,53 @@ Examples:
     train.bak.png
     sunset.bak.gif
 
+- Only print the journald log lines between 12:03:44 and the end of the 12:05
+  minute:
+
+    {binary_name} 'ft:Oct 17 12:03:44-Oct 17 12:05'
+
+- Only print the log lines in the 200ms after 0:00:24.7 and those in the last
+  30 seconds of the log:
+
+    {binary_name} ft:0:00:24.7+200ms 'ft:-30s$'
+
+- Narrow a verbose log recorded with GST_DEBUG=*:3,webkit*:6,GST_PADS:5 to
+  the lines of the webkit categories (except webkitmse) and the warnings and
+  errors of the rest, highlighting each category in a different color:
+
+    {binary_name} 'cat:*:3,webkit*:6,-webkitmse' hc:
+
+- Select the lines logged by the sourcebuffer0 object, highlighting "append"
+  only in the function names:
+
+    {binary_name} 'fc@object:^sourcebuffer0$' h@function:append
+
+- Highlight errors in bold red over a yellow background and warnings in
+  orange, using the colorblind friendly theme for the rest of the commands:
+
+    {binary_name} --theme=colorblind 'fc{{red,bold,on_yellow}}:error' \
+      'fc{{#ff8800}}:warn' ht:
+
+- Render the pipeline as it was at 0:00:05, from a log recorded with
+  GST_DEBUG=GST_PADS:5,GST_ELEMENT_PADS:5:
+
+    {binary_name} --topology --topology-at=0:00:05 -f gst.log | dot -Tsvg \
+      > pipeline.svg
+
+- Check that the buffers reaching the pads have monotonic timestamps without
+  holes nor overlaps longer than 10ms, printing only the lines with problems:
+
+    {binary_name} pts:10ms 'fc:\[(pts|dts) went back|\[(hole|overlap) of'
+
+- Show how the caps of the pads changed when they were renegotiated:
+
+    {binary_name} fc:'caps = ' pc:diff
+
+- Select lines with "sourcebuffer" and "append" or with "demux" but without
+  "eos" (quoted, so the shell doesn't interpret the operators):
+
+    {binary_name} 'e:(sourcebuffer & append) | (demux & !eos)'
+
 - Only print the log lines with times between 0:00:24.787450146 and
   0:00:24.790741865 or those at 0:00:30.492576587 or after and highlight every
   thread in a different color (shown as [0x1ee2320] and <0x1f01598> in the
@@ -91,6 +299,41 @@ Examples:
 
 const OPTION_HELP_SHORT: &str = "-h";
 const OPTION_HELP: &str = "--help";
+const OPTION_FILE_SHORT: &str = "-f";
+const OPTION_FILE: &str = "--file";
+const OPTION_FOLLOW_SHORT: &str = "-F";
+const OPTION_FOLLOW: &str = "--follow";
+const OPTION_WITH_FILENAME_SHORT: &str = "-H";
+const OPTION_WITH_FILENAME: &str = "--with-filename";
+const OPTION_KEEP_WHITESPACE_SHORT: &str = "-k";
+const OPTION_KEEP_WHITESPACE: &str = "--keep-whitespace";
+const OPTION_AFTER_CONTEXT_SHORT: &str = "-A";
+const OPTION_AFTER_CONTEXT: &str = "--after-context";
+const OPTION_BEFORE_CONTEXT_SHORT: &str = "-B";
+const OPTION_BEFORE_CONTEXT: &str = "--before-context";
+const OPTION_CONTEXT_SHORT: &str = "-C";
+const OPTION_CONTEXT: &str = "--context";
+const OPTION_TIME_FORMAT: &str = "--time-format";
+const OPTION_TIME_FORMAT_VALUE: &str = "--time-format=";
+const OPTION_STRIP_ANSI: &str = "--strip-ansi";
+const OPTION_KEEP_ANSI: &str = "--keep-ansi";
+const OPTION_COLOR: &str = "--color=";
+const OPTION_THEME: &str = "--theme=";
+const OPTION_STABLE_COLORS: &str = "--stable-colors";
+const OPTION_TOPOLOGY: &str = "--topology";
+const OPTION_TOPOLOGY_FORMAT: &str = "--topology=";
+const OPTION_TOPOLOGY_AT: &str = "--topology-at=";
+const OPTION_STATE_TIMELINE: &str = "--state-timeline";
+const OPTION_END_OF_COMMANDS: &str = "--";
+const STDIN_FILENAME: &str = "-";
+
+// Magic bytes at the start of compressed streams.
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const XZ_MAGIC: &[u8] = &[0xfd, b'7', b'z', b'X', b'Z', 0x00];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+// Time to wait before checking again for new data when following a file.
+const FOLLOW_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(250);
 const OPTION_FILTER: &str = "fc:";
 const OPTION_FILTER_NO_HIGHLIGHT: &str = "fn:";
 const OPTION_HIGHLIGHT: &str = "h:";
@@ -98,41 +341,249 @@ const OPTION_NEGATIVE_FILTER: &str = "n:";
 const OPTION_SUBSTITUTION: &str = "s:";
 const OPTION_FILTER_TIME: &str = "ft:";
 const OPTION_HIGHLIGHT_THREADS: &str = "ht:";
+const OPTION_EXPRESSION: &str = "e:";
+const OPTION_TIME_DELTA: &str = "dt:";
+const OPTION_GAP: &str = "gap:";
+const OPTION_FILTER_LEVEL: &str = "lvl:";
+const OPTION_FILTER_CATEGORY: &str = "cat:";
+const OPTION_HIGHLIGHT_CATEGORIES: &str = "hc:";
+const OPTION_HIGHLIGHT_OBJECTS: &str = "ho:";
+const OPTION_CHECK_TIMESTAMPS: &str = "pts:";
+const OPTION_PRETTY_CAPS: &str = "pc:";
+// Commands which can have a pinned style with "{STYLE}" (eg: "fc{red,bold}:error").
+const STYLED_COMMANDS: [&str; 3] = ["fc", "h", "e"];
+// Commands which can be scoped to a field with "@FIELD" (eg: "fc@object:sourcebuffer0").
+const FIELD_SCOPED_COMMANDS: [&str; 5] = ["fc", "fn", "h", "n", "s"];
+
+// Named palettes of styles used for the highlighting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Theme {
+    // Every foreground/background combination of the basic 16 colors.
+    Classic,
+    // Curated foreground/background pairs, readable on dark terminals.
+    Dark,
+    // Curated foreground/background pairs, readable on light terminals.
+    Light,
+    HighContrast,
+    // Based on the Okabe-Ito palette, distinguishable with the common color vision deficiencies.
+    Colorblind,
+}
+
+type Rgb = (u8, u8, u8);
+
+impl Theme {
+    pub fn from_name(name: &str) -> Option<Theme> {
+        match name.to_ascii_lowercase().as_str() {
+            "classic" => Some(Theme::Classic),
+            "dark" => Some(Theme::Dark),
+            "light" => Some(Theme::Light),
+            "high-contrast" => Some(Theme::HighContrast),
+            "colorblind" => Some(Theme::Colorblind),
+            _ => None,
+        }
+    }
+
+    // Sequence of (foreground, background) colors of the curated themes.
+    fn colors(self) -> Option<&'static [(Rgb, Rgb)]> {
+        match self {
+            Theme::Classic => None,
+            Theme::Dark => Some(&[
+                ((255, 95, 95), (64, 0, 0)),
+                ((95, 215, 95), (0, 52, 0)),
+                ((255, 215, 95), (60, 46, 0)),
+                ((95, 175, 255), (0, 28, 70)),
+                ((215, 135, 255), (44, 0, 64)),
+                ((95, 215, 215), (0, 48, 48)),
+                ((255, 175, 95), (70, 34, 0)),
+                ((215, 215, 215), (52, 52, 52)),
+            ]),
+            Theme::Light => Some(&[
+                ((175, 0, 0), (255, 215, 215)),
+                ((0, 115, 0), (215, 255, 215)),
+                ((135, 95, 0), (255, 245, 200)),
+                ((0, 0, 175), (215, 230, 255)),
+                ((135, 0, 135), (245, 215, 255)),
+                ((0, 110, 110), (210, 250, 250)),
+                ((175, 85, 0), (255, 230, 200)),
+                ((60, 60, 60), (230, 230, 230)),
+            ]),
+            Theme::HighContrast => Some(&[
+                ((0, 0, 0), (255, 255, 0)),
+                ((255, 255, 255), (0, 0, 255)),
+                ((0, 0, 0), (0, 255, 255)),
+                ((255, 255, 255), (200, 0, 0)),
+                ((0, 0, 0), (0, 255, 0)),
+                ((255, 255, 255), (160, 0, 160)),
+                ((0, 0, 0), (255, 255, 255)),
+            ]),
+            Theme::Colorblind => Some(&[
+                ((0, 0, 0), (230, 159, 0)),
+                ((0, 0, 0), (86, 180, 233)),
+                ((0, 0, 0), (0, 158, 115)),
+                ((0, 0, 0), (240, 228, 66)),
+                ((255, 255, 255), (0, 114, 178)),
+                ((0, 0, 0), (213, 94, 0)),
+                ((0, 0, 0), (204, 121, 167)),
+            ]),
+        }
+    }
+}
+
+// Theme and color capabilities of the terminal, used to build the styles.
+#[derive(Debug, Clone, Copy)]
+pub struct Palette {
+    pub theme: Theme,
+    // Whether the terminal supports 24-bit colors. Otherwise, they're downgraded to the closest
+    // color of the 256-color palette.
+    pub truecolor: bool,
+}
+
+impl Palette {
+    pub fn new(theme: Theme) -> Palette {
+        Palette {
+            theme,
+            truecolor: std::env::var("COLORTERM")
+                .is_ok_and(|value| value == "truecolor" || value == "24bit"),
+        }
+    }
+
+    pub fn colour(&self, (r, g, b): Rgb) -> Colour {
+        if self.truecolor {
+            Colour::RGB(r, g, b)
+        } else {
+            Colour::Fixed(rgb_to_256(r, g, b))
+        }
+    }
+
+    // Parses a style like "red,bold", "#ff8800,on_blue,underline" or "208,on_#202020".
+    pub fn parse_style(&self, text: &str) -> anyhow::Result<Style> {
+        let mut style = Style::new();
+        for token in text.split(',').map(str::trim) {
+            style = match token.to_ascii_lowercase().as_str() {
+                "bold" => style.bold(),
+                "dimmed" | "dim" => style.dimmed(),
+                "italic" => style.italic(),
+                "underline" => style.underline(),
+                "reverse" => style.reverse(),
+                token => match token.strip_prefix("on_") {
+                    Some(colour) => style.on(self.parse_colour(colour)?),
+                    None => style.fg(self.parse_colour(token)?),
+                },
+            };
+        }
+        Ok(style)
+    }
+
+    // Parses a color name (eg: "red"), a 256-color palette number or a "#rrggbb" RGB color.
+    pub fn parse_colour(&self, text: &str) -> anyhow::Result<Colour> {
+        let colour = match text {
+            "black" => Some(Colour::Black),
+            "red" => Some(Colour::Red),
+            "green" => Some(Colour::Green),
+            "yellow" => Some(Colour::Yellow),
+            "blue" => Some(Colour::Blue),
+            "purple" | "magenta" => Some(Colour::Purple),
+            "cyan" => Some(Colour::Cyan),
+            "white" => Some(Colour::White),
+            _ => match text.strip_prefix('#') {
+                Some(hex) if hex.len() == 6 => u32::from_str_radix(hex, 16)
+                    .ok()
+                    .map(|rgb| self.colour(((rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8))),
+                Some(_) => None,
+                None => text.parse::<u8>().ok().map(Colour::Fixed),
+            },
+        };
+        colour.ok_or_else(|| {
+            anyhow::anyhow!(format!(
+                "Invalid color \"{:}\". Use a name (black, red, green, yellow, blue, purple, cyan, white), a number from 0 to 255 or #rrggbb",
+                text
+            ))
+        })
+    }
+}
+
+// Returns the closest color of the xterm 256-color palette: either from its 6x6x6 color cube
+// or from its grayscale ramp.
+pub fn rgb_to_256(r: u8, g: u8, b: u8) -> u8 {
+    let cube_index = |value: u8| match value {
+        0..48 => 0,
+        48..115 => 1,
+        _ => (value - 35) / 40,
+    };
+    let cube_value = |index: u8| if index == 0 { 0 } else { 55 + 40 * index };
+    let distance = |(r2, g2, b2): Rgb| {
+        let square = |a: u8, b: u8| (a as i32 - b as i32).pow(2);
+        square(r, r2) + square(g, g2) + square(b, b2)
+    };
+    let (ri, gi, bi) = (cube_index(r), cube_index(g), cube_index(b));
+    let cube = (cube_value(ri), cube_value(gi), cube_value(bi));
+    let average = (r as u16 + g as u16 + b as u16) / 3;
+    let gray_index = (average.saturating_sub(3) / 10).min(23) as u8;
+    let gray_value = 8 + 10 * gray_index;
+    if distance((gray_value, gray_value, gray_value)) < distance(cube) {
+        232 + gray_index
+    } else {
+        16 + 36 * ri + 6 * gi + bi
+    }
+}
 
 #[derive(Debug)]
 pub struct StyleIterator {
-    count: u8,
-    fg: u8,
-    bg: u8,
+    palette: Palette,
+    count: usize,
     reverse: bool,
     bold: bool,
     underline: bool,
 }
 
 impl StyleIterator {
-    pub fn new(reverse: bool, bold: bool, underline: bool) -> StyleIterator {
+    // Number of foreground/background combinations of the 16 basic colors (without the ones
+    // with the same foreground and background).
+    const CLASSIC_COMBINATIONS: usize = 16 * 15;
+
+    pub fn new(palette: Palette, reverse: bool, bold: bool, underline: bool) -> StyleIterator {
         StyleIterator {
+            palette,
             count: 0,
-            fg: 0,
-            bg: 0,
             reverse,
             bold,
             underline,
         }
     }
 
-    fn forward(&mut self) {
-        loop {
-            self.fg = (self.fg + 1) % 16;
-            if self.fg == 0 {
-                self.fg = 0;
-                self.bg = (self.bg + 1) % 16;
+    // Returns the style in the position of the sequence, which repeats itself when the theme
+    // runs out of colors.
+    pub fn style_at(&self, index: usize) -> Style {
+        let (fg, bg) = match self.palette.theme.colors() {
+            Some(colors) => {
+                let (fg, bg) = colors[index % colors.len()];
+                (self.palette.colour(fg), self.palette.colour(bg))
             }
-            if self.fg != self.bg {
-                break;
+            None => {
+                // Every foreground for a background, skipping the foreground equal to it.
+                let index = index % StyleIterator::CLASSIC_COMBINATIONS;
+                let bg = index / 15;
+                let fg = index % 15;
+                let fg = if fg >= bg { fg + 1 } else { fg };
+                (Colour::Fixed(fg as u8), Colour::Fixed(bg as u8))
             }
+        };
+        let mut result = Style::new().on(bg).fg(fg);
+        if self.reverse {
+            result = result.reverse();
         }
-        self.count += 1;
+        if self.bold {
+            result = result.bold();
+        }
+        if self.underline {
+            result = result.underline();
+        }
+        result
+    }
+
+    // Returns always the same style for the same id, no matter the order in which ids are seen.
+    pub fn style_for(&self, id: &[u8]) -> Style {
+        self.style_at(fnv1a(&id.to_ascii_lowercase()) as usize)
     }
 }
 
@@ -140,24 +591,211 @@ impl Iterator for StyleIterator {
     type Item = Style;
 
     fn next(&mut self) -> Option<Style> {
-        self.forward();
-        let mut result = Style::new()
-            .on(Colour::Fixed(self.bg))
-            .fg(Colour::Fixed(self.fg));
-        if self.reverse {
-            result = result.reverse();
+        self.count += 1;
+        Some(self.style_at(self.count - 1))
+    }
+}
+
+// 64-bit FNV-1a hash, which is stable across runs and platforms (unlike the std hasher).
+pub fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+// Returns the style if the output is colored, or a plain style (which adds no escape sequences)
+// otherwise.
+pub fn style_if(color: bool, style: Style) -> Style {
+    if color { style } else { Style::new() }
+}
+
+// Like Style::paint(), but for text which may not be valid UTF-8.
+pub fn paint_bytes(style: &Style, text: &[u8]) -> Vec<u8> {
+    let mut result = style.prefix().to_string().into_bytes();
+    result.extend_from_slice(text);
+    result.extend_from_slice(style.suffix().to_string().as_bytes());
+    result
+}
+
+// A replacement done in the text of a line: the range the replaced text had and the range that
+// the replacement text has now.
+#[derive(Debug)]
+pub struct Edit {
+    pub old: std::ops::Range<usize>,
+    pub new: std::ops::Range<usize>,
+}
+
+// Highlighted ranges of the text of a line. They're recorded while the commands are applied and
+// rendered as ANSI escape sequences only once at the end, so the commands always see the plain
+// text. When ranges overlap, the one added last wins.
+#[derive(Debug, Default)]
+pub struct Highlights {
+    spans: Vec<(std::ops::Range<usize>, Style)>,
+    // Escape sequences of the input kept at their positions of the text (see AnsiInput::Keep).
+    escapes: Vec<(usize, Vec<u8>)>,
+}
+
+impl Highlights {
+    pub fn add(&mut self, range: std::ops::Range<usize>, style: Style) {
+        if !range.is_empty() {
+            self.spans.push((range, style));
         }
-        if self.bold {
-            result = result.bold();
+    }
+
+    // Highlights the text matched by the regex in the line or, if there's a field, in that field
+    // of the line (parsed, if it's a GStreamer log line).
+    pub fn add_matches(
+        &mut self,
+        line: &[u8],
+        parsed: Option<&GstLogLine>,
+        field: Option<GstField>,
+        regex: &Regex,
+        style: Style,
+    ) {
+        let Some(range) = field_range(line, parsed, field) else {
+            return;
+        };
+        for found in regex.find_iter(&line[range.clone()]) {
+            self.add(
+                range.start + found.start()..range.start + found.end(),
+                style,
+            );
         }
-        if self.underline {
-            result = result.underline();
+    }
+
+    // Removes the escape sequences from the line, keeping them to be rendered again at the
+    // same positions of the returned text.
+    pub fn keep_escapes(&mut self, line: &[u8]) -> Vec<u8> {
+        let (text, positions) = strip_ansi(line);
+        let mut previous_end = 0;
+        for (i, position) in positions.iter().enumerate() {
+            if *position > previous_end {
+                self.escapes
+                    .push((i, line[previous_end..*position].to_vec()));
+            }
+            previous_end = position + 1;
+        }
+        text
+    }
+
+    // Moves the ranges to follow the text after some replacements. A range touching a replaced
+    // text is extended to cover the whole replacement.
+    pub fn apply_edits(&mut self, edits: &[Edit]) {
+        if edits.is_empty() {
+            return;
+        }
+        let remap = |position: usize, is_end: bool| {
+            let mut delta: isize = 0;
+            for edit in edits {
+                if (!is_end && edit.old.contains(&position))
+                    || (is_end && position > edit.old.start && position <= edit.old.end)
+                {
+                    return if is_end { edit.new.end } else { edit.new.start };
+                }
+                if edit.old.end > position || (is_end && edit.old.start == position) {
+                    break;
+                }
+                delta += edit.new.len() as isize - edit.old.len() as isize;
+            }
+            position.saturating_add_signed(delta)
+        };
+        for (range, _) in &mut self.spans {
+            *range = remap(range.start, false)..remap(range.end, true);
+        }
+        for (position, _) in &mut self.escapes {
+            *position = remap(*position, false);
+        }
+    }
+
+    // Returns the text with the highlighted ranges (only if the output is colored) and the kept
+    // escape sequences rendered.
+    pub fn render(&self, text: &[u8], color: bool) -> Vec<u8> {
+        let spans: &[(std::ops::Range<usize>, Style)] = if color { &self.spans } else { &[] };
+        if spans.is_empty() && self.escapes.is_empty() {
+            return text.to_vec();
+        }
+        // Index of the span which applies to every byte of the text.
+        let mut styles: Vec<Option<usize>> = vec![None; text.len()];
+        for (index, (range, _)) in spans.iter().enumerate() {
+            let range = range.start.min(text.len())..range.end.min(text.len());
+            styles[range].fill(Some(index));
         }
-        Some(result)
+
+        let mut result = Vec::with_capacity(text.len() * 2);
+        let mut escapes = self.escapes.iter().peekable();
+        // Kept escape sequences in effect, to be restored after every highlighted range.
+        let mut active_escapes: Vec<u8> = Vec::new();
+        let mut current: Option<Style> = None;
+        for i in 0..=text.len() {
+            let style = styles.get(i).copied().flatten().map(|index| spans[index].1);
+            if style != current {
+                if let Some(current) = current {
+                    result.extend_from_slice(current.suffix().to_string().as_bytes());
+                    result.extend_from_slice(&active_escapes);
+                }
+                if let Some(style) = style {
+                    result.extend_from_slice(style.prefix().to_string().as_bytes());
+                }
+                current = style;
+            }
+            while let Some((_, escape)) = escapes.next_if(|(position, _)| *position <= i) {
+                if [&b"\x1b[0m"[..], b"\x1b[00m", b"\x1b[m"]
+                    .iter()
+                    .any(|reset| escape.ends_with(reset))
+                {
+                    active_escapes.clear();
+                } else {
+                    active_escapes.extend_from_slice(escape);
+                }
+                // Inside a highlighted range, they'd override its style.
+                if current.is_none() {
+                    result.extend_from_slice(escape);
+                }
+            }
+            if i < text.len() {
+                result.push(text[i]);
+            }
+        }
+        result
     }
 }
 
-#[derive(PartialEq, Debug)]
+// What to do with the ANSI escape sequences already present in the input lines (eg: colors of
+// GST_DEBUG_COLOR_MODE=on or of the output of cargo test).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnsiInput {
+    // They're handled like any other text.
+    Raw,
+    // They're removed before applying the commands.
+    Strip,
+    // They're kept in the output, but the commands match on a copy of the line without them.
+    Keep,
+}
+
+// When to color the output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorMode {
+    // Only when the output is a terminal and the NO_COLOR environment variable isn't set.
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorMode {
+    pub fn is_enabled(self) -> bool {
+        match self {
+            ColorMode::Always => true,
+            ColorMode::Never => false,
+            // See https://no-color.org.
+            ColorMode::Auto => {
+                std::env::var_os("NO_COLOR").is_none_or(|value| value.is_empty())
+                    && std::io::stdout().is_terminal()
+            }
+        }
+    }
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
 pub enum LineSelection {
     Neutral,
     ExplicitlyAllowed,
@@ -165,29 +803,228 @@ pub enum LineSelection {
 }
 
 #[derive(Debug)]
-pub struct HighlightThreadsIdData {
+pub struct HighlightIdData {
     pub style: Style,
     pub regex: Regex,
 }
 
+// Assigns a different style to each id (eg: thread ids, debug categories) the first time
+// it's seen, and highlights it with that style from then on.
 #[derive(Debug)]
-pub struct HighlightThreadsState {
-    pub ids: HashMap</* id */ String, /* data */ HighlightThreadsIdData>,
+pub struct HighlightIdsState {
+    pub ids: HashMap</* id */ Vec<u8>, /* data */ HighlightIdData>,
     pub styles: StyleIterator,
+    // Derive the style from a hash of the id, instead of taking the next one, so every id
+    // always has the same style, whatever the ids seen before.
+    pub stable: bool,
+    // Styles chosen by the user for some (lowercase) ids.
+    pub pinned: HashMap<Vec<u8>, Style>,
 }
 
-impl HighlightThreadsState {
-    pub fn new() -> HighlightThreadsState {
-        HighlightThreadsState {
+impl HighlightIdsState {
+    pub fn new(styles: StyleIterator, stable: bool) -> HighlightIdsState {
+        HighlightIdsState {
             ids: HashMap::new(),
-            styles: StyleIterator::new(true, true, false),
+            styles,
+            stable,
+            pinned: HashMap::new(),
+        }
+    }
+
+    // Parses a list of ids with pinned styles, like "0x1ee2320=red,bold,0x1f01598=#00ff00". As
+    // styles can't contain "=", the tokens without it belong to the style of the previous id.
+    pub fn pin_styles(
+        &mut self,
+        text: &str,
+        palette: &Palette,
+        command: &str,
+    ) -> anyhow::Result<()> {
+        let mut entries: Vec<(&str, String)> = Vec::new();
+        for token in text.split(',').filter(|token| !token.is_empty()) {
+            match (token.split_once('='), entries.last_mut()) {
+                (Some((id, style)), _) if !id.is_empty() => entries.push((id, style.to_string())),
+                (None, Some((_, style))) => {
+                    style.push(',');
+                    style.push_str(token);
+                }
+                _ => {
+                    return Err(anyhow::anyhow!(format!(
+                        "Invalid parameter \"{:}\" in command \"{:}\". Examples: {:} {:}ID=red {:}ID=red,bold,ID2=#00ff00",
+                        token, command, command, command, command
+                    )));
+                }
+            }
+        }
+        for (id, style) in entries {
+            self.pinned.insert(
+                id.to_ascii_lowercase().into_bytes(),
+                palette.parse_style(&style)?,
+            );
+        }
+        Ok(())
+    }
+
+    fn new_style(&mut self, id: &[u8]) -> Style {
+        if let Some(style) = self.pinned.get(&id.to_ascii_lowercase()) {
+            *style
+        } else if self.stable {
+            self.styles.style_for(id)
+        } else {
+            self.styles.next().unwrap()
+        }
+    }
+
+    // Returns the style of the id, assigning one to it if it's new.
+    pub fn style(&mut self, id: &[u8]) -> Style {
+        if !self.ids.contains_key(id) {
+            let pattern = regex::escape(&String::from_utf8_lossy(id));
+            let style = self.new_style(id);
+            self.ids.insert(
+                id.to_vec(),
+                HighlightIdData {
+                    style,
+                    regex: RegexBuilder::new(&pattern)
+                        .case_insensitive(true)
+                        .build()
+                        .unwrap(),
+                },
+            );
         }
+        self.ids.get(id).unwrap().style
+    }
+
+    // Highlights every occurrence of the id in the line.
+    pub fn highlight(&mut self, id: &[u8], line: &[u8], highlights: &mut Highlights) {
+        let style = self.style(id);
+        let data = self.ids.get(id).unwrap();
+        highlights.add_matches(line, None, None, &data.regex, style);
     }
 }
 
-impl Default for HighlightThreadsState {
-    fn default() -> Self {
-        HighlightThreadsState::new()
+// Assigns a style to each element appearing in the object references of GStreamer logs (eg:
+// "<qtdemux0>" or "<qtdemux0:audio_0>"), and a shade of that style to each of its pads.
+#[derive(Debug)]
+pub struct HighlightObjectsState {
+    pub elements: HighlightIdsState,
+    // Styles of the pads, by "element:pad".
+    pub pads: HashMap<Vec<u8>, Style>,
+    // Number of pads seen for every element.
+    pub pad_counts: HashMap<Vec<u8>, usize>,
+    pub palette: Palette,
+    pub regex: Regex,
+}
+
+impl HighlightObjectsState {
+    // Factors applied to the element colors to get the shades of its pads.
+    const SHADES: [f32; 6] = [0.75, 1.3, 0.55, 1.6, 0.4, 1.9];
+
+    pub fn new(styles: StyleIterator, stable: bool, palette: Palette) -> HighlightObjectsState {
+        HighlightObjectsState {
+            elements: HighlightIdsState::new(styles, stable),
+            pads: HashMap::new(),
+            pad_counts: HashMap::new(),
+            palette,
+            regex: Regex::new(r"<([\w.\-]+)(?::([\w%.\-]+))?>").unwrap(),
+        }
+    }
+
+    // Highlights every object reference in the line.
+    pub fn highlight(&mut self, line: &[u8], highlights: &mut Highlights) {
+        for captures in self.regex.captures_iter(line) {
+            let element = captures.get(1).unwrap();
+            let style = self.elements.style(element.as_bytes());
+            highlights.add(element.range(), style);
+            if let Some(pad) = captures.get(2) {
+                let key = [element.as_bytes(), b":", pad.as_bytes()].concat();
+                let style = match self.pads.get(&key) {
+                    Some(style) => *style,
+                    None => {
+                        let count = self
+                            .pad_counts
+                            .entry(element.as_bytes().to_vec())
+                            .or_default();
+                        let index = if self.elements.stable {
+                            fnv1a(&pad.as_bytes().to_ascii_lowercase()) as usize
+                        } else {
+                            *count
+                        };
+                        *count += 1;
+                        let shade = HighlightObjectsState::SHADES
+                            [index % HighlightObjectsState::SHADES.len()];
+                        let shaded = self.shade(style, shade);
+                        self.pads.insert(key, shaded);
+                        shaded
+                    }
+                };
+                highlights.add(pad.range(), style);
+            }
+        }
+    }
+
+    // Returns the style with its colors made darker (factor < 1) or lighter (factor > 1).
+    fn shade(&self, mut style: Style, factor: f32) -> Style {
+        let scale = |colour: Option<Colour>| {
+            colour.map(|colour| {
+                let (r, g, b) = colour_to_rgb(colour);
+                let channel = |value: u8| {
+                    if factor < 1.0 {
+                        (value as f32 * factor) as u8
+                    } else {
+                        (value as f32 + (255.0 - value as f32) * (factor - 1.0) / factor) as u8
+                    }
+                };
+                self.palette.colour((channel(r), channel(g), channel(b)))
+            })
+        };
+        style.foreground = scale(style.foreground);
+        style.background = scale(style.background);
+        style
+    }
+}
+
+// Returns the RGB values of a color, using the xterm defaults for the palette colors.
+pub fn colour_to_rgb(colour: Colour) -> Rgb {
+    const BASIC: [Rgb; 16] = [
+        (0, 0, 0),
+        (205, 0, 0),
+        (0, 205, 0),
+        (205, 205, 0),
+        (0, 0, 238),
+        (205, 0, 205),
+        (0, 205, 205),
+        (229, 229, 229),
+        (127, 127, 127),
+        (255, 0, 0),
+        (0, 255, 0),
+        (255, 255, 0),
+        (92, 92, 255),
+        (255, 0, 255),
+        (0, 255, 255),
+        (255, 255, 255),
+    ];
+    let index = match colour {
+        Colour::Black => 0,
+        Colour::Red => 1,
+        Colour::Green => 2,
+        Colour::Yellow => 3,
+        Colour::Blue => 4,
+        Colour::Purple => 5,
+        Colour::Cyan => 6,
+        Colour::White => 7,
+        Colour::Fixed(index) => index,
+        Colour::RGB(r, g, b) => return (r, g, b),
+    };
+    match index {
+        0..16 => BASIC[index as usize],
+        16..232 => {
+            let index = index - 16;
+            let value = |i: u8| if i == 0 { 0 } else { 55 + 40 * i };
+            (value(index / 36), value(index / 6 % 6), value(index % 6))
+        }
+        _ => {
+            let value = 8 + 10 * (index - 232);
+            (value, value, value)
+        }
     }
 }
 
@@ -195,88 +1032,2100 @@ impl Default for HighlightThreadsState {
 pub struct MultilineSelectionState {
     // Signals if a multiple line selection block has started or not.
     pub multiline_selection: LineSelection,
-    // Used by the MultilineSelection algorithm in process_line() to set
-    // multiline_selection = ExplicitlyForbidden when the next line is processed.
-    pub forbid_next_line: bool,
+    // Selection at the start of every input (forbidden until a time range begins, if any).
+    pub initial_selection: LineSelection,
 }
 
-#[derive(Debug)]
-pub enum Command {
-    // Discards the line if no substring matches Filter, otherwise highlights the matched text
-    Filter(
-        Regex,
-        Style,
-        /* negative */ bool,
-        /* highlight */ bool,
-    ),
-    // Highlights the matched text (if present). Doesn't discard the current line.
-    Highlight(Regex, Style),
-    // Searches and replaces the matched text (if present). Doesn't discard the current line.
-    Substitution(Regex, String),
-    // Filters lines that start with a timestamp (a number) and are between begin and end
-    // values. If begin or end and empty strings, they are ignored.
-    FilterTime(
-        /* time_regex */ Regex,
-        /* begin */ String,
-        /* end */ String,
-    ),
-    // Assuming a GStreamer log format, locates the different thread ids and assigns a different
-    // style to each of them.
-    HighlightThreads,
+const NANOS_PER_SECOND: u128 = 1_000_000_000;
+
+// Parses a time like "0:01:10.881123150" (H:MM:SS.fraction, also M:SS.fraction),
+// "70.88" (seconds) or "70881ms" (with ns, us, ms, s, m or h units). Returns the time and
+// its precision, which is the smallest difference that the text can express (eg: 10ms
+// for "70.88").
+pub fn parse_time(text: &str) -> Option<(Duration, Duration)> {
+    const UNITS: [(&str, u128); 6] = [
+        ("ns", 1),
+        ("us", 1_000),
+        ("ms", 1_000_000),
+        ("s", NANOS_PER_SECOND),
+        ("m", 60 * NANOS_PER_SECOND),
+        ("h", 3600 * NANOS_PER_SECOND),
+    ];
+    fn is_number(text: &str) -> bool {
+        !text.is_empty() && text.bytes().all(|c| c.is_ascii_digit())
+    }
+
+    let unit = UNITS
+        .iter()
+        .find_map(|(suffix, unit)| text.strip_suffix(suffix).map(|number| (number, *unit)));
+    let (number, unit) = unit.unwrap_or((text, NANOS_PER_SECOND));
+    let mut parts: Vec<&str> = number.split(':').collect();
+    // Units can't be used with the H:MM:SS format.
+    if parts.len() > 3 || (parts.len() > 1 && unit != NANOS_PER_SECOND) {
+        return None;
+    }
+    let seconds = parts.pop()?;
+    let (integer, fraction) = seconds.split_once('.').unwrap_or((seconds, ""));
+    if !parts.iter().all(|part| is_number(part))
+        || !is_number(integer)
+        || !(fraction.is_empty() || is_number(fraction))
+    {
+        return None;
+    }
+    let mut whole: u128 = 0;
+    for part in parts.iter().chain(std::iter::once(&integer)) {
+        whole = whole.checked_mul(60)?.checked_add(part.parse().ok()?)?;
+    }
+    make_time(whole, fraction, unit)
 }
 
-#[derive(Debug)]
-pub enum CommandLineOption {
-    // -h, --help.
-    Help,
+// Parses a duration, which must have a unit (eg: "200ms", "1.5s"), so it can't be
+// mistaken for a time.
+pub fn parse_duration(text: &str) -> Option<Duration> {
+    if !text.ends_with(|c: char| c.is_ascii_alphabetic()) {
+        return None;
+    }
+    parse_time(text).map(|(duration, _)| duration)
 }
 
-// Holds the context to process each line. Context would be a list of words to
-// match (with colors), things to memorize, or other kind of commands to be
-// done on lines. It should be like a list of commands to apply to lines.
+// Builds a time from a whole number of units and the decimal digits of the fraction of
+// the unit. Returns the time and its precision, which depends on the number of digits.
+fn make_time(whole: u128, fraction: &str, unit: u128) -> Option<(Duration, Duration)> {
+    // Digits beyond the nanosecond can't be represented anyway.
+    let fraction = &fraction[..fraction.len().min(20)];
+    let scale = 10u128.pow(fraction.len() as u32);
+    let fraction_value: u128 = if fraction.is_empty() {
+        0
+    } else {
+        fraction.parse().ok()?
+    };
+    let nanos = whole.checked_mul(unit)? + fraction_value * unit / scale;
+    let precision = (unit / scale).max(1);
+    Some((
+        Duration::from_nanos(u64::try_from(nanos).ok()?),
+        Duration::from_nanos(precision as u64),
+    ))
+}
+
+// Layouts of the timestamps in the lines.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TimeFormat {
+    // Detected from the first line having a recognizable timestamp (see TimeParser).
+    Auto,
+    // Time at the start of the line, like in GStreamer logs (0:01:10.881123150).
+    GStreamer,
+    // Month name, day and time at the start of the line, like in syslog and
+    // journald (Oct 17 12:03:44).
+    Syslog,
+    // Month, day and time at the start of the line, like in Android logcat
+    // (10-17 12:03:44.123).
+    Logcat,
+    // Date and time anywhere in the line, like in JSON logs (2025-10-17T12:03:44.123Z).
+    Iso8601,
+}
+
+impl TimeFormat {
+    // The more specific formats go first, so they aren't mistaken for the generic ones.
+    const DETECTION_ORDER: [TimeFormat; 4] = [
+        TimeFormat::Logcat,
+        TimeFormat::Syslog,
+        TimeFormat::GStreamer,
+        TimeFormat::Iso8601,
+    ];
+
+    pub fn from_name(name: &str) -> Option<TimeFormat> {
+        match name.to_lowercase().as_str() {
+            "auto" => Some(TimeFormat::Auto),
+            "gst" | "gstreamer" => Some(TimeFormat::GStreamer),
+            "syslog" | "journald" => Some(TimeFormat::Syslog),
+            "logcat" | "android" => Some(TimeFormat::Logcat),
+            "iso" | "iso8601" => Some(TimeFormat::Iso8601),
+            _ => None,
+        }
+    }
+}
+
+const MONTH_NAMES: [&str; 12] = [
+    "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+];
+
+// Number of days since 1970-01-01 of a date in the proleptic Gregorian calendar.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = if year >= 0 { year } else { year - 399 } / 400;
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+// Locates and parses the timestamps of the lines and the bounds of the time filters.
+// Wall clock times are converted to the time since 1970-01-01 (or since the start of the
+// year when it isn't specified), and those with a UTC offset are converted to UTC.
 #[derive(Debug)]
-pub struct Context {
-    // Command line options other than line processing commands.
-    pub options: VecDeque<CommandLineOption>,
-    // Sequence of commands to apply to each line.
-    pub commands: VecDeque<Command>,
-    // Internal global states needed for some commands.
-    pub multiline_selection_state: MultilineSelectionState,
-    pub highlight_threads_state: HighlightThreadsState,
+pub struct TimeParser {
+    // Format of the lines. When Auto, it's replaced by the detected one.
+    pub format: TimeFormat,
+    // Format requested by the user, restored at the start of every input.
+    configured_format: TimeFormat,
+    // Lines with an ISO-8601 time which isn't at their start, found while detecting the format.
+    iso8601_lines: usize,
+    regexes: Vec<(TimeFormat, Regex)>,
 }
 
-impl Context {
-    pub fn new(args: Vec<String>) -> anyhow::Result<Self> {
-        let mut options: VecDeque<CommandLineOption> = VecDeque::new();
-        let mut styles = StyleIterator::new(false, true, true);
-        let mut commands: VecDeque<Command> = VecDeque::new();
-        let mut multiline_selection = LineSelection::Neutral;
-        let time_regex = RegexBuilder::new(r"^[0-9][0-9:.]*")
-            .case_insensitive(true)
-            .build();
+impl TimeParser {
+    // An ISO-8601 time in the middle of the text (eg: in JSON logs) can also be part of the
+    // message of a line without time (eg: a header), so that format is only detected after
+    // finding it in several lines without any timestamp at their start.
+    const ISO8601_DETECTION_LINES: usize = 3;
 
-        for mut arg in args {
-            if arg.starts_with("-") {
-                if arg == OPTION_HELP || arg == OPTION_HELP_SHORT {
-                    options.push_back(CommandLineOption::Help);
-                    break; // Don't process any other option.
+    pub fn new(format: TimeFormat) -> TimeParser {
+        // Seconds are optional, so bounds can be written with less precision (eg: "12:04").
+        const TIME: &str =
+            r"(?<hour>\d{2}):(?<minute>\d{2})(?::(?<second>\d{2})(?:[.,](?<fraction>\d+))?)?";
+        let patterns = [
+            (
+                TimeFormat::GStreamer,
+                r"^(?<time>[0-9][0-9:.]*)(?:\s|$)".to_string(),
+            ),
+            (
+                TimeFormat::Syslog,
+                format!(
+                    r"^(?<month>[a-z]{{3}})[a-z]*\s+(?<day>\d{{1,2}})\s+{}",
+                    TIME
+                ),
+            ),
+            (
+                TimeFormat::Logcat,
+                format!(r"^(?<month>\d{{2}})-(?<day>\d{{2}})\s+{}", TIME),
+            ),
+            (
+                TimeFormat::Iso8601,
+                format!(
+                    r"(?<year>\d{{4}})-(?<month>\d{{2}})-(?<day>\d{{2}})[T ]{}(?<zone>Z|[+-]\d{{2}}:?\d{{2}})?",
+                    TIME
+                ),
+            ),
+        ];
+        TimeParser {
+            format,
+            configured_format: format,
+            iso8601_lines: 0,
+            regexes: patterns
+                .iter()
+                .map(|(format, pattern)| {
+                    (
+                        *format,
+                        RegexBuilder::new(pattern)
+                            .case_insensitive(true)
+                            .build()
+                            .unwrap(),
+                    )
+                })
+                .collect(),
+        }
+    }
+
+    fn regex(&self, format: TimeFormat) -> &Regex {
+        &self.regexes.iter().find(|(f, _)| *f == format).unwrap().1
+    }
+
+    // Parses the time in the text, which must be in the specified (not Auto) format. If
+    // whole is true, the timestamp must span the whole text. Returns the time and its
+    // precision.
+    fn parse_as(
+        &self,
+        format: TimeFormat,
+        text: &[u8],
+        whole: bool,
+    ) -> Option<(Duration, Duration)> {
+        if format == TimeFormat::GStreamer && whole {
+            // Bounds can also use seconds with units.
+            return parse_time(std::str::from_utf8(text).ok()?);
+        }
+        let captures = self.regex(format).captures(text)?;
+        let found = captures.get(0)?;
+        if whole && (found.start() != 0 || found.end() != text.len()) {
+            return None;
+        }
+        let field = |name: &str| {
+            captures
+                .name(name)
+                .and_then(|m| std::str::from_utf8(m.as_bytes()).ok())
+        };
+        if format == TimeFormat::GStreamer {
+            return parse_time(field("time")?);
+        }
+        let number = |name: &str| field(name).and_then(|value| value.parse::<i64>().ok());
+        let month = match number("month") {
+            Some(month) => month,
+            None => {
+                let name = field("month")?.to_lowercase();
+                MONTH_NAMES.iter().position(|m| *m == name)? as i64 + 1
+            }
+        };
+        let (day, hour, minute) = (number("day")?, number("hour")?, number("minute")?);
+        let second = number("second");
+        if !(1..=12).contains(&month)
+            || !(1..=31).contains(&day)
+            || hour > 23
+            || minute > 59
+            || second.is_some_and(|second| second > 60)
+        {
+            return None;
+        }
+        let days = match number("year") {
+            Some(year) => days_from_civil(year, month, day),
+            // Without year, the times are only comparable inside the same year.
+            None => days_from_civil(1970, month, day),
+        };
+        let offset = match field("zone") {
+            None | Some("Z") | Some("z") => 0,
+            Some(zone) => {
+                let digits = zone[1..].replace(':', "");
+                let offset = digits[0..2].parse::<i64>().ok()? * 3600
+                    + digits[2..4].parse::<i64>().ok()? * 60;
+                if zone.starts_with('-') {
+                    -offset
                 } else {
-                    return Err(anyhow::anyhow!(format!(
-                        "Invalid option: {:}. Use -h for help.",
-                        arg
-                    )));
-                }
-            } else if arg.starts_with(OPTION_FILTER_NO_HIGHLIGHT) {
-                arg = arg.drain(OPTION_FILTER_NO_HIGHLIGHT.len()..).collect();
-                let regex = RegexBuilder::new(&arg).case_insensitive(true).build();
-                if regex.is_err() {
-                    return Err(anyhow::anyhow!(format!("{:?}", regex.err().unwrap())));
+                    offset
+                }
+            }
+        };
+        let seconds = days * 86400 + hour * 3600 + minute * 60 + second.unwrap_or(0) - offset;
+        let (time, precision) = make_time(
+            u128::try_from(seconds).ok()?,
+            field("fraction").unwrap_or(""),
+            NANOS_PER_SECOND,
+        )?;
+        match second {
+            Some(_) => Some((time, precision)),
+            None => Some((time, Duration::from_secs(60))),
+        }
+    }
+
+    // Finds the timestamp of the line (ignoring leading whitespace) and parses it. On Auto
+    // format, the first recognized format is used from then on.
+    pub fn parse_line(&mut self, line: &[u8]) -> Option<Duration> {
+        let line = line.trim_ascii_start();
+        if self.format != TimeFormat::Auto {
+            return self
+                .parse_as(self.format, line, false)
+                .map(|(time, _)| time);
+        }
+        for format in TimeFormat::DETECTION_ORDER {
+            if let Some((time, _)) = self.parse_as(format, line, false) {
+                if format == TimeFormat::Iso8601
+                    && self
+                        .regex(format)
+                        .find(line)
+                        .is_some_and(|found| found.start() != 0)
+                {
+                    self.iso8601_lines += 1;
+                    if self.iso8601_lines < TimeParser::ISO8601_DETECTION_LINES {
+                        return None;
+                    }
+                }
+                self.format = format;
+                return Some(time);
+            }
+        }
+        None
+    }
+
+    // Restores the configured format, so it's detected again (eg: for the next input).
+    pub fn reset(&mut self) {
+        self.format = self.configured_format;
+        self.iso8601_lines = 0;
+    }
+
+    // Parses a time filter bound and returns its format, time and precision.
+    pub fn parse_bound(&self, text: &str) -> Option<(TimeFormat, Duration, Duration)> {
+        let formats = if self.format == TimeFormat::Auto {
+            &TimeFormat::DETECTION_ORDER[..]
+        } else {
+            std::slice::from_ref(&self.format)
+        };
+        formats.iter().find_map(|format| {
+            self.parse_as(*format, text.as_bytes(), true)
+                .map(|(time, precision)| (*format, time, precision))
+        })
+    }
+
+    // Parses a "[BEGIN]-[END]" time range. As dates can also contain dashes, the first
+    // dash that splits the text into valid bounds (of the same format) is the delimiter.
+    #[allow(clippy::type_complexity)]
+    pub fn parse_range(
+        &self,
+        text: &str,
+    ) -> Option<(Option<(Duration, Duration)>, Option<(Duration, Duration)>)> {
+        let parse = |bound: &str| {
+            if bound.is_empty() {
+                Some(None)
+            } else {
+                self.parse_bound(bound).map(Some)
+            }
+        };
+        for (position, _) in text.match_indices('-') {
+            let (Some(begin), Some(end)) = (parse(&text[..position]), parse(&text[position + 1..]))
+            else {
+                continue;
+            };
+            if let (Some((begin_format, _, _)), Some((end_format, _, _))) = (begin, end)
+                && begin_format != end_format
+            {
+                continue;
+            }
+            return Some((
+                begin.map(|(_, time, precision)| (time, precision)),
+                end.map(|(_, time, precision)| (time, precision)),
+            ));
+        }
+        None
+    }
+}
+
+// Keeps track of the lines printed as context of the selected lines, like "grep -A/-B/-C".
+#[derive(Debug, Default)]
+pub struct ContextLinesState {
+    // Number of lines to print before and after each selected line.
+    pub before: usize,
+    pub after: usize,
+    // Lines not selected yet, which might be printed if a selected line comes soon.
+    pub pending: VecDeque<Vec<u8>>,
+    // Number of lines still to be printed after the last selected line.
+    pub after_remaining: usize,
+    // Number of the line being processed (starting on 1) and of the last printed one.
+    pub line_number: u64,
+    pub last_printed_line_number: Option<u64>,
+    // Signals if anything has been printed yet, to know if a separator is needed.
+    pub printed_any: bool,
+}
+
+impl ContextLinesState {
+    pub fn is_enabled(&self) -> bool {
+        self.before > 0 || self.after > 0
+    }
+}
+
+// The result of processing a line.
+#[derive(PartialEq, Debug)]
+pub enum LineOutput {
+    // The line has been selected and must be printed, with all its highlights.
+    Selected(Vec<u8>),
+    // The line has been rejected by a filter, but can still be printed as context of a
+    // selected line. Holds the line text without highlights.
+    Context(Vec<u8>),
+    // The line must never be printed (eg: it's out of the selected time range).
+    Discarded,
+}
+
+// Boolean expression tree of regexes, used by the Expression command. The regexes
+// themselves are stored in the command, and the leaves refer to them by index.
+#[derive(Debug)]
+pub enum Expression {
+    Regex(usize),
+    Not(Box<Expression>),
+    And(Box<Expression>, Box<Expression>),
+    Or(Box<Expression>, Box<Expression>),
+}
+
+impl Expression {
+    // Evaluates the expression, given the result of matching each of the regexes.
+    pub fn evaluate(&self, matches: &[bool]) -> bool {
+        match self {
+            Expression::Regex(index) => matches[*index],
+            Expression::Not(e) => !e.evaluate(matches),
+            Expression::And(a, b) => a.evaluate(matches) && b.evaluate(matches),
+            Expression::Or(a, b) => a.evaluate(matches) || b.evaluate(matches),
+        }
+    }
+}
+
+// Recursive descent parser for expressions with this grammar:
+//   or     := and ('|' and)*
+//   and    := not ('&' not)*
+//   not    := '!' not | '(' or ')' | REGEX
+// The REGEX is either a "double quoted" string or a sequence of characters without
+// whitespace or operators (unless escaped with '\').
+pub struct ExpressionParser<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+    // Regex patterns found so far, and whether they're negated or not.
+    pub patterns: Vec<(String, /* negated */ bool)>,
+    negated: bool,
+}
+
+impl<'a> ExpressionParser<'a> {
+    pub fn new(text: &'a str) -> ExpressionParser<'a> {
+        ExpressionParser {
+            chars: text.chars().peekable(),
+            patterns: Vec::new(),
+            negated: false,
+        }
+    }
+
+    pub fn parse(&mut self) -> anyhow::Result<Expression> {
+        let expression = self.parse_or()?;
+        match self.peek() {
+            None => Ok(expression),
+            Some(c) => Err(anyhow::anyhow!(format!(
+                "Unexpected \"{:}\" in expression",
+                c
+            ))),
+        }
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        while self.chars.next_if(|c| c.is_whitespace()).is_some() {}
+        self.chars.peek().copied()
+    }
+
+    fn parse_or(&mut self) -> anyhow::Result<Expression> {
+        let mut expression = self.parse_and()?;
+        while self.peek() == Some('|') {
+            self.chars.next();
+            expression = Expression::Or(Box::new(expression), Box::new(self.parse_and()?));
+        }
+        Ok(expression)
+    }
+
+    fn parse_and(&mut self) -> anyhow::Result<Expression> {
+        let mut expression = self.parse_not()?;
+        while self.peek() == Some('&') {
+            self.chars.next();
+            expression = Expression::And(Box::new(expression), Box::new(self.parse_not()?));
+        }
+        Ok(expression)
+    }
+
+    fn parse_not(&mut self) -> anyhow::Result<Expression> {
+        match self.peek() {
+            Some('!') => {
+                self.chars.next();
+                self.negated = !self.negated;
+                let expression = self.parse_not();
+                self.negated = !self.negated;
+                Ok(Expression::Not(Box::new(expression?)))
+            }
+            Some('(') => {
+                self.chars.next();
+                let expression = self.parse_or()?;
+                if self.peek() != Some(')') {
+                    return Err(anyhow::anyhow!("Missing \")\" in expression"));
+                }
+                self.chars.next();
+                Ok(expression)
+            }
+            Some(_) => self.parse_regex(),
+            None => Err(anyhow::anyhow!("Unexpected end of expression")),
+        }
+    }
+
+    fn parse_regex(&mut self) -> anyhow::Result<Expression> {
+        let mut pattern = String::new();
+        if self.chars.next_if_eq(&'"').is_some() {
+            loop {
+                match self.chars.next() {
+                    Some('"') => break,
+                    Some('\\') if self.chars.next_if_eq(&'"').is_some() => pattern.push('"'),
+                    Some(c) => pattern.push(c),
+                    None => return Err(anyhow::anyhow!("Missing closing quote in expression")),
+                }
+            }
+        } else {
+            while let Some(c) = self
+                .chars
+                .next_if(|c| !c.is_whitespace() && !"&|!()".contains(*c))
+            {
+                pattern.push(c);
+                // The escaped character is kept escaped, so the regex still sees it as a literal.
+                if c == '\\'
+                    && let Some(escaped) = self.chars.next()
+                {
+                    pattern.push(escaped);
+                }
+            }
+        }
+        if pattern.is_empty() {
+            return Err(anyhow::anyhow!("Empty regex in expression"));
+        }
+        self.patterns.push((pattern, self.negated));
+        Ok(Expression::Regex(self.patterns.len() - 1))
+    }
+}
+
+// GStreamer debug levels, from the most to the least severe.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum DebugLevel {
+    Error = 1,
+    Warning = 2,
+    Fixme = 3,
+    Info = 4,
+    Debug = 5,
+    Log = 6,
+    Trace = 7,
+    Memdump = 9,
+}
+
+impl DebugLevel {
+    pub const ALL: [DebugLevel; 8] = [
+        DebugLevel::Error,
+        DebugLevel::Warning,
+        DebugLevel::Fixme,
+        DebugLevel::Info,
+        DebugLevel::Debug,
+        DebugLevel::Log,
+        DebugLevel::Trace,
+        DebugLevel::Memdump,
+    ];
+
+    // Parses the level as it appears in the log (eg: "WARN") or in the command line,
+    // where the number can also be used (eg: "2").
+    pub fn from_name(name: &[u8]) -> Option<DebugLevel> {
+        match name.to_ascii_uppercase().as_slice() {
+            b"ERROR" | b"1" => Some(DebugLevel::Error),
+            b"WARNING" | b"WARN" | b"2" => Some(DebugLevel::Warning),
+            b"FIXME" | b"3" => Some(DebugLevel::Fixme),
+            b"INFO" | b"4" => Some(DebugLevel::Info),
+            b"DEBUG" | b"5" => Some(DebugLevel::Debug),
+            b"LOG" | b"6" => Some(DebugLevel::Log),
+            b"TRACE" | b"7" => Some(DebugLevel::Trace),
+            b"MEMDUMP" | b"9" => Some(DebugLevel::Memdump),
+            _ => None,
+        }
+    }
+}
+
+// Returns the text without ANSI escape sequences (like the ones in the logs recorded with
+// GST_DEBUG_COLOR_MODE=on) and, for every byte of that text (and for its end), its
+// position in the original text.
+pub fn strip_ansi(text: &[u8]) -> (Vec<u8>, Vec<usize>) {
+    let mut stripped = Vec::with_capacity(text.len());
+    let mut positions = Vec::with_capacity(text.len() + 1);
+    let mut i = 0;
+    while i < text.len() {
+        if text[i] != 0x1b {
+            stripped.push(text[i]);
+            positions.push(i);
+            i += 1;
+            continue;
+        }
+        i += 1;
+        match text.get(i) {
+            // CSI sequence (eg: "\x1b[1;31m"): parameters, intermediates and a final byte.
+            Some(b'[') => {
+                i += 1;
+                while i < text.len() && !(0x40..=0x7e).contains(&text[i]) {
+                    i += 1;
+                }
+                i += 1;
+            }
+            // OSC sequence (eg: hyperlinks), terminated by BEL or by ST ("\x1b\\").
+            Some(b']') => {
+                while i < text.len() && text[i] != 0x07 && !text[i..].starts_with(b"\x1b\\") {
+                    i += 1;
+                }
+                i += if text[i..].starts_with(b"\x1b\\") {
+                    2
+                } else {
+                    1
+                };
+            }
+            Some(_) => i += 1,
+            None => {}
+        }
+    }
+    positions.push(text.len());
+    (stripped, positions)
+}
+
+// Fields of a GStreamer log line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GstField {
+    Timestamp,
+    Pid,
+    Thread,
+    Level,
+    Category,
+    File,
+    Line,
+    Function,
+    Object,
+    Message,
+}
+
+impl GstField {
+    pub const NAMES: [&str; 10] = [
+        "timestamp",
+        "pid",
+        "thread",
+        "level",
+        "category",
+        "file",
+        "line",
+        "function",
+        "object",
+        "message",
+    ];
+
+    pub fn from_name(name: &str) -> Option<GstField> {
+        match name.to_ascii_lowercase().as_str() {
+            "timestamp" => Some(GstField::Timestamp),
+            "pid" => Some(GstField::Pid),
+            "thread" => Some(GstField::Thread),
+            "level" => Some(GstField::Level),
+            "category" => Some(GstField::Category),
+            "file" => Some(GstField::File),
+            "line" => Some(GstField::Line),
+            "function" => Some(GstField::Function),
+            "object" => Some(GstField::Object),
+            "message" => Some(GstField::Message),
+            _ => None,
+        }
+    }
+}
+
+// A parsed GStreamer log line, with the layout of the default log function:
+// "0:00:01.234567890  1234 0x55d5c7a0 DEBUG  category file.c:12:function:<object> message".
+// ANSI escape sequences are ignored, so the lines can come from GST_DEBUG_COLOR_MODE=on logs
+// or from our own already highlighted output. The fields are kept as ranges of the text without
+// those sequences, and can be mapped back to the original line.
+#[derive(Debug)]
+pub struct GstLogLine {
+    // Text of the line without ANSI escape sequences.
+    pub text: Vec<u8>,
+    // Position in the original line of each byte of the text (and of its end).
+    positions: Vec<usize>,
+    // Ranges of the text, indexed by GstField. The object is the only optional field.
+    fields: [Option<std::ops::Range<usize>>; 10],
+}
+
+impl GstLogLine {
+    pub fn parse(line: &[u8]) -> Option<GstLogLine> {
+        let (text, positions) = strip_ansi(line);
+        let mut fields: [Option<std::ops::Range<usize>>; 10] = Default::default();
+
+        // The first 5 fields are separated by whitespace.
+        let mut end = 0;
+        for field in fields.iter_mut().take(GstField::Category as usize + 1) {
+            let begin = end + text[end..].iter().position(|c| !c.is_ascii_whitespace())?;
+            end = begin
+                + text[begin..]
+                    .iter()
+                    .position(|c| c.is_ascii_whitespace())
+                    .unwrap_or(text.len() - begin);
+            *field = Some(begin..end);
+        }
+        let field_text = |field: GstField| &text[fields[field as usize].clone().unwrap()];
+        if parse_time(std::str::from_utf8(field_text(GstField::Timestamp)).ok()?).is_none()
+            || !field_text(GstField::Pid).iter().all(u8::is_ascii_digit)
+            || DebugLevel::from_name(field_text(GstField::Level)).is_none()
+        {
+            return None;
+        }
+
+        // Then comes "file:line:function:<object>" (the object being optional) and the message.
+        let begin = end + text[end..].iter().position(|c| !c.is_ascii_whitespace())?;
+        let file_end = begin + text[begin..].iter().position(|c| *c == b':')?;
+        let line_end = file_end + 1 + text[file_end + 1..].iter().position(|c| *c == b':')?;
+        if line_end == file_end + 1 || !text[file_end + 1..line_end].iter().all(u8::is_ascii_digit)
+        {
+            return None;
+        }
+        // C++ functions can contain "::", so the function ends at the first colon followed by
+        // the object, whitespace or the end of the line.
+        let function_end = (line_end + 1..text.len()).find(|i| {
+            text[*i] == b':'
+                && matches!(
+                    text.get(i + 1),
+                    None | Some(b'<') | Some(b' ') | Some(b'\t')
+                )
+        })?;
+        fields[GstField::File as usize] = Some(begin..file_end);
+        fields[GstField::Line as usize] = Some(file_end + 1..line_end);
+        fields[GstField::Function as usize] = Some(line_end + 1..function_end);
+        let mut end = function_end + 1;
+        if text.get(end) == Some(&b'<')
+            && let Some(object_end) = (end..text.len()).find(|i| {
+                text[*i] == b'>' && matches!(text.get(i + 1), None | Some(b' ') | Some(b'\t'))
+            })
+        {
+            fields[GstField::Object as usize] = Some(end + 1..object_end);
+            end = object_end + 1;
+        }
+        if text.get(end).is_some_and(u8::is_ascii_whitespace) {
+            end += 1;
+        }
+        fields[GstField::Message as usize] = Some(end..text.len());
+
+        Some(GstLogLine {
+            text,
+            positions,
+            fields,
+        })
+    }
+
+    // Text of the field, without ANSI escape sequences.
+    pub fn field(&self, field: GstField) -> Option<&[u8]> {
+        self.fields[field as usize]
+            .clone()
+            .map(|range| &self.text[range])
+    }
+
+    // Range of the field in the original line. Any escape sequence surrounding the field is
+    // left out.
+    pub fn original_range(&self, field: GstField) -> Option<std::ops::Range<usize>> {
+        self.fields[field as usize].clone().map(|range| {
+            if range.is_empty() {
+                self.positions[range.start]..self.positions[range.start]
+            } else {
+                self.positions[range.start]..self.positions[range.end - 1] + 1
+            }
+        })
+    }
+
+    pub fn level(&self) -> Option<DebugLevel> {
+        self.field(GstField::Level).and_then(DebugLevel::from_name)
+    }
+}
+
+// Range of the field in the line or, if there's no field, the whole line. The fields come from
+// the already parsed line, as lines which aren't GStreamer log lines (None) have no fields.
+pub fn field_range(
+    line: &[u8],
+    parsed: Option<&GstLogLine>,
+    field: Option<GstField>,
+) -> Option<std::ops::Range<usize>> {
+    match field {
+        None => Some(0..line.len()),
+        Some(field) => parsed.and_then(|parsed| parsed.original_range(field)),
+    }
+}
+
+// Replaces the text matched by the regex like Regex::replace_all(), but only inside the field of
+// the line (if any), and also returns the replacements done.
+pub fn replace_in_field(
+    line: &[u8],
+    parsed: Option<&GstLogLine>,
+    field: Option<GstField>,
+    regex: &Regex,
+    replacement: &[u8],
+) -> (Vec<u8>, Vec<Edit>) {
+    let Some(range) = field_range(line, parsed, field) else {
+        return (line.to_vec(), Vec::new());
+    };
+    let mut result = line[..range.start].to_vec();
+    let mut edits = Vec::new();
+    let mut last_end = range.start;
+    for captures in regex.captures_iter(&line[range.clone()]) {
+        let found = captures.get(0).unwrap();
+        let old = range.start + found.start()..range.start + found.end();
+        result.extend_from_slice(&line[last_end..old.start]);
+        let new_start = result.len();
+        captures.expand(replacement, &mut result);
+        last_end = old.end;
+        edits.push(Edit {
+            old,
+            new: new_start..result.len(),
+        });
+    }
+    result.extend_from_slice(&line[last_end..]);
+    (result, edits)
+}
+
+// Returns whether the regex matches the line or, if there's a field, that field of the line.
+pub fn is_match_in_field(
+    line: &[u8],
+    parsed: Option<&GstLogLine>,
+    field: Option<GstField>,
+    regex: &Regex,
+) -> bool {
+    match field {
+        None => regex.is_match(line),
+        Some(field) => parsed
+            .and_then(|parsed| parsed.field(field).map(|text| regex.is_match(text)))
+            .unwrap_or(false),
+    }
+}
+
+// A rule of a category filter, like the ones in GST_DEBUG (eg: "webkit*:5").
+#[derive(Debug)]
+pub struct CategoryRule {
+    // Glob pattern of the category names, converted to a regex.
+    pub pattern: Regex,
+    // Least severe level selected. None means that the category is excluded.
+    pub max_level: Option<DebugLevel>,
+}
+
+impl CategoryRule {
+    // Parses a rule like "GST_PADS", "webkit*:5" or "-GST_PADS" (exclusion).
+    pub fn parse(text: &str) -> anyhow::Result<CategoryRule> {
+        let (glob, max_level) = match text.strip_prefix('-') {
+            Some(glob) => (glob, None),
+            None => match text.split_once(':') {
+                Some((glob, level)) if level == "0" || level.eq_ignore_ascii_case("none") => {
+                    (glob, None)
+                }
+                Some((glob, level)) => match DebugLevel::from_name(level.as_bytes()) {
+                    Some(level) => (glob, Some(level)),
+                    None => {
+                        return Err(anyhow::anyhow!(format!(
+                            "Invalid level \"{:}\" in category filter command \"cat:\"",
+                            level
+                        )));
+                    }
+                },
+                None => (text, Some(DebugLevel::Memdump)),
+            },
+        };
+        if glob.is_empty() {
+            return Err(anyhow::anyhow!(
+                "Empty category in category filter command \"cat:\". Examples: cat:webkit*,-GST_PADS cat:*:3,webkit*:6"
+            ));
+        }
+        let mut pattern = String::from("^");
+        for c in glob.chars() {
+            match c {
+                '*' => pattern.push_str(".*"),
+                '?' => pattern.push('.'),
+                c => pattern.push_str(&regex::escape(&c.to_string())),
+            }
+        }
+        pattern.push('$');
+        Ok(CategoryRule {
+            pattern: RegexBuilder::new(&pattern).case_insensitive(true).build()?,
+            max_level,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub enum TimeRange {
+    // Times between begin and end values (both included). If begin or end are None, they
+    // are ignored.
+    Absolute(
+        /* begin */ Option<Duration>,
+        /* end */ Option<Duration>,
+    ),
+    // Times in the first duration of the input, counting from its first timestamp.
+    FromStart(Duration),
+    // Times in the last duration of the input, counting back from its last timestamp. It
+    // requires buffering the lines in that duration until the end of the input is reached.
+    ToEnd(Duration),
+}
+
+impl TimeRange {
+    // Returns the begin and end times of the range. They may still be unknown for relative
+    // ranges, in which case the range can't select anything yet.
+    pub fn resolve(&self, state: &TimeFilterState) -> Option<(Option<Duration>, Option<Duration>)> {
+        match self {
+            TimeRange::Absolute(begin, end) => Some((*begin, *end)),
+            TimeRange::FromStart(duration) => state
+                .first_time
+                .map(|first| (Some(first), Some(first + *duration))),
+            TimeRange::ToEnd(duration) => state
+                .last_time
+                .map(|last| (Some(last.saturating_sub(*duration)), None)),
+        }
+    }
+}
+
+// Times of the printed lines, used by the TimeDelta command.
+#[derive(Debug, Default)]
+pub struct TimeDeltaState {
+    pub first_time: Option<Duration>,
+    pub previous_time: Option<Duration>,
+}
+
+// Last buffer timestamps seen for an object, used by the CheckTimestamps command.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct BufferTimestamps {
+    pub pts: Option<Duration>,
+    pub dts: Option<Duration>,
+    // PTS + duration, which is where the next buffer is expected to start.
+    pub end: Option<Duration>,
+}
+
+// Buffer timestamps of every object (eg: "h264parse0:sink") of the current input.
+#[derive(Debug)]
+pub struct TimestampsState {
+    pub objects: HashMap<Vec<u8>, BufferTimestamps>,
+    pub regex: Regex,
+}
+
+impl TimestampsState {
+    pub fn new() -> TimestampsState {
+        TimestampsState {
+            objects: HashMap::new(),
+            regex: RegexBuilder::new(
+                r"\b(pts|dts|dur|duration)\b\s*[:=]?\s*(?:\(\w+\)\s*)?(\d+:\d{2}:\d{2}\.\d+)",
+            )
+            .case_insensitive(true)
+            .build()
+            .unwrap(),
+        }
+    }
+
+    // Parses a GstClockTime, where "99:99:99.999999999" means GST_CLOCK_TIME_NONE.
+    fn parse_clock_time(text: &[u8]) -> Option<Duration> {
+        if text.starts_with(b"99:99:99") {
+            return None;
+        }
+        parse_time(std::str::from_utf8(text).ok()?).map(|(time, _)| time)
+    }
+
+    // Checks the PTS, DTS and duration of the line against the previous ones of the same
+    // object, and returns the problems found with the range of the value involved in each one.
+    pub fn check(
+        &mut self,
+        line: &[u8],
+        object: &[u8],
+        threshold: Duration,
+    ) -> Vec<(String, std::ops::Range<usize>)> {
+        // PTS, DTS and duration, with their ranges in the line.
+        let mut values: [Option<(std::ops::Range<usize>, Option<Duration>)>; 3] =
+            Default::default();
+        for captures in self.regex.captures_iter(line) {
+            let index = match captures[1].to_ascii_lowercase().as_slice() {
+                b"pts" => 0,
+                b"dts" => 1,
+                _ => 2,
+            };
+            let value = captures.get(2).unwrap();
+            values[index].get_or_insert((
+                value.range(),
+                TimestampsState::parse_clock_time(value.as_bytes()),
+            ));
+        }
+        let [pts, dts, duration] = values;
+        if pts.is_none() && dts.is_none() {
+            return Vec::new();
+        }
+        let previous = self.objects.get(object).copied().unwrap_or_default();
+
+        let mut problems = Vec::new();
+        if let Some((range, Some(dts))) = &dts
+            && let Some(previous_dts) = previous.dts
+            && *dts < previous_dts
+        {
+            let text = format!("dts went back {}s", format_seconds(previous_dts - *dts));
+            problems.push((text, range.clone()));
+        }
+        if let Some((range, Some(pts))) = &pts
+            && let Some(previous_pts) = previous.pts
+        {
+            if *pts < previous_pts {
+                // With a DTS, the PTS can go back legitimately (eg: B-frames).
+                if !matches!(dts, Some((_, Some(_)))) {
+                    let text = format!("pts went back {}s", format_seconds(previous_pts - *pts));
+                    problems.push((text, range.clone()));
+                }
+            } else if let Some(end) = previous.end {
+                if pts.saturating_sub(end) > threshold {
+                    let text = format!("hole of {}s", format_seconds(*pts - end));
+                    problems.push((text, range.clone()));
+                } else if end.saturating_sub(*pts) > threshold {
+                    let text = format!("overlap of {}s", format_seconds(end - *pts));
+                    problems.push((text, range.clone()));
+                }
+            }
+        }
+
+        let pts = pts.and_then(|(_, pts)| pts);
+        let dts = dts.and_then(|(_, dts)| dts);
+        let duration = duration.and_then(|(_, duration)| duration);
+        self.objects.insert(
+            object.to_vec(),
+            BufferTimestamps {
+                pts: pts.or(previous.pts),
+                dts: dts.or(previous.dts),
+                end: pts.zip(duration).map(|(pts, duration)| pts + duration),
+            },
+        );
+        problems
+    }
+}
+
+impl Default for TimestampsState {
+    fn default() -> Self {
+        TimestampsState::new()
+    }
+}
+
+// A GstStructure (eg: one of the structures of some GstCaps), as found in the text of a line.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CapsStructure {
+    // Name, including the caps features, if any (eg: "video/x-raw(memory:GLMemory)").
+    pub name: Vec<u8>,
+    // Names and values of the fields, including their type (eg: "width", "(int)1920").
+    pub fields: Vec<(Vec<u8>, Vec<u8>)>,
+}
+
+impl CapsStructure {
+    fn is_name_char(c: u8) -> bool {
+        c.is_ascii_alphanumeric() || b"/_.+-".contains(&c)
+    }
+
+    fn is_field_name_char(c: u8) -> bool {
+        c.is_ascii_alphanumeric() || b"_.-".contains(&c)
+    }
+
+    // Length of the field value at the beginning of the text, which ends at the first ",", ";"
+    // or whitespace outside of quotes and brackets.
+    fn value_len(text: &[u8]) -> usize {
+        let mut depth = 0;
+        let mut quoted = false;
+        let mut escaped = false;
+        for (i, c) in text.iter().enumerate() {
+            if escaped {
+                escaped = false;
+                continue;
+            }
+            match c {
+                b'\\' => escaped = true,
+                b'"' => quoted = !quoted,
+                _ if quoted => {}
+                b'(' | b'[' | b'{' | b'<' => depth += 1,
+                b')' | b']' | b'}' | b'>' => {
+                    if depth == 0 {
+                        return i;
+                    }
+                    depth -= 1;
+                }
+                b',' | b';' if depth == 0 => return i,
+                c if c.is_ascii_whitespace() && depth == 0 => return i,
+                _ => {}
+            }
+        }
+        text.len()
+    }
+
+    // Parses the structure at the beginning of the text, returning it and its length.
+    fn parse(text: &[u8]) -> Option<(CapsStructure, usize)> {
+        if !text.first().is_some_and(u8::is_ascii_alphabetic) {
+            return None;
+        }
+        let mut position = text
+            .iter()
+            .position(|c| !CapsStructure::is_name_char(*c))
+            .unwrap_or(text.len());
+        if text[position..].starts_with(b"(") {
+            position += text[position..].iter().position(|c| *c == b')')? + 1;
+        }
+        let name = text[..position].to_vec();
+        let mut fields = Vec::new();
+        while let Some(rest) = text[position..].strip_prefix(b",") {
+            let field_start = position + 1 + rest.len() - rest.trim_ascii_start().len();
+            let field_text = &text[field_start..];
+            let name_len = field_text
+                .iter()
+                .position(|c| !CapsStructure::is_field_name_char(*c))
+                .unwrap_or(field_text.len());
+            if name_len == 0 || field_text.get(name_len) != Some(&b'=') {
+                break;
+            }
+            let value_start = field_start + name_len + 1;
+            let value_len = CapsStructure::value_len(&text[value_start..]);
+            if value_len == 0 {
+                break;
+            }
+            fields.push((
+                field_text[..name_len].to_vec(),
+                text[value_start..value_start + value_len].to_vec(),
+            ));
+            position = value_start + value_len;
+        }
+        Some((CapsStructure { name, fields }, position))
+    }
+
+    // Parses the GstCaps (one or more structures separated by ";") at the beginning of the
+    // text, returning them and their length. To avoid finding them in any text with commas,
+    // the first structure must have a field and the field must have a type, like in the
+    // strings serialized by GStreamer.
+    fn parse_caps(text: &[u8]) -> Option<(Vec<CapsStructure>, usize)> {
+        let (first, mut end) = CapsStructure::parse(text)?;
+        if !first
+            .fields
+            .first()
+            .is_some_and(|(_, value)| value.starts_with(b"("))
+        {
+            return None;
+        }
+        let mut structures = vec![first];
+        while let Some(rest) = text[end..].strip_prefix(b";") {
+            let start = end + 1 + rest.len() - rest.trim_ascii_start().len();
+            // Structures without fields must be media types (eg: "video/x-raw"), so the text
+            // after a trailing ";" isn't mistaken for one.
+            match CapsStructure::parse(&text[start..]) {
+                Some((structure, len))
+                    if !structure.fields.is_empty() || structure.name.contains(&b'/') =>
+                {
+                    structures.push(structure);
+                    end = start + len;
+                }
+                _ => break,
+            }
+        }
+        Some((structures, end))
+    }
+
+    // Finds all the GstCaps and GstStructures in the line.
+    pub fn find_all(line: &[u8]) -> Vec<Vec<CapsStructure>> {
+        let mut found = Vec::new();
+        let mut position = 0;
+        while position < line.len() {
+            if (position == 0 || !CapsStructure::is_name_char(line[position - 1]))
+                && let Some((caps, len)) = CapsStructure::parse_caps(&line[position..])
+            {
+                found.push(caps);
+                position += len.max(1);
+                continue;
+            }
+            position += 1;
+        }
+        found
+    }
+}
+
+// Last GstCaps seen for every object (eg: "h264parse0:sink") of the current input, used by the
+// PrettyCaps command to show what changed between them.
+#[derive(Debug, Default)]
+pub struct CapsState {
+    pub objects: HashMap<Vec<u8>, Vec<CapsStructure>>,
+}
+
+impl CapsState {
+    // Appends the GstCaps and GstStructures of the line to it, with a field in each line, and
+    // marks the changes since the previous caps of the same object, if requested.
+    pub fn append(
+        &mut self,
+        line: &mut Vec<u8>,
+        object: &[u8],
+        highlights: &mut Highlights,
+        diff: bool,
+    ) {
+        let all_caps = CapsStructure::find_all(line);
+        if all_caps.is_empty() {
+            return;
+        }
+        let mut append = |marker: &str, text: &[u8], style: Option<Style>| {
+            line.extend_from_slice(b"\n    ");
+            let start = line.len() + marker.len() - marker.trim_start().len();
+            line.extend_from_slice(marker.as_bytes());
+            line.extend_from_slice(text);
+            if let Some(style) = style {
+                highlights.add(start..line.len(), style);
+            }
+        };
+        for caps in all_caps {
+            let previous = if diff {
+                self.objects.insert(object.to_vec(), caps.clone())
+            } else {
+                None
+            };
+            for (i, structure) in caps.iter().enumerate() {
+                let previous_structure = previous.as_ref().and_then(|previous| previous.get(i));
+                match previous_structure {
+                    Some(previous_structure) if previous_structure.name != structure.name => {
+                        let mut text = previous_structure.name.clone();
+                        text.extend_from_slice(b" -> ");
+                        text.extend_from_slice(&structure.name);
+                        append("~ ", &text, Some(Colour::Yellow.bold()));
+                    }
+                    None if previous.is_some() => {
+                        append("+ ", &structure.name, Some(Colour::Green.bold()))
+                    }
+                    _ => append("", &structure.name, Some(Style::new().bold())),
+                }
+                for (name, value) in &structure.fields {
+                    let mut text = name.clone();
+                    text.extend_from_slice(b": ");
+                    let previous_value = previous_structure.and_then(|previous_structure| {
+                        previous_structure
+                            .fields
+                            .iter()
+                            .find(|(previous_name, _)| previous_name == name)
+                            .map(|(_, previous_value)| previous_value)
+                    });
+                    match previous_value {
+                        Some(previous_value) if previous_value != value => {
+                            text.extend_from_slice(previous_value);
+                            text.extend_from_slice(b" -> ");
+                            text.extend_from_slice(value);
+                            append("  ~ ", &text, Some(Colour::Yellow.normal()));
+                        }
+                        None if previous.is_some() => {
+                            text.extend_from_slice(value);
+                            append("  + ", &text, Some(Colour::Green.normal()));
+                        }
+                        _ => {
+                            text.extend_from_slice(value);
+                            append("    ", &text, None);
+                        }
+                    }
+                }
+                // The fields that the structure doesn't have anymore.
+                for (name, value) in previous_structure.iter().flat_map(|s| &s.fields) {
+                    if structure
+                        .fields
+                        .iter()
+                        .all(|(field_name, _)| field_name != name)
+                    {
+                        let mut text = name.clone();
+                        text.extend_from_slice(b": ");
+                        text.extend_from_slice(value);
+                        append("  - ", &text, Some(Colour::Red.normal()));
+                    }
+                }
+            }
+            // The structures that the caps don't have anymore.
+            for structure in previous
+                .iter()
+                .flat_map(|previous| previous.iter().skip(caps.len()))
+            {
+                append("- ", &structure.name, Some(Colour::Red.bold()));
+            }
+        }
+    }
+}
+
+// Output formats of the pipeline topology.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TopologyFormat {
+    // Graphviz DOT graph.
+    Dot,
+    // Indented text, with every element and its pads.
+    Tree,
+}
+
+// Pipeline topology reconstructed from the pad addition/removal (GST_ELEMENT_PADS) and link/unlink
+// (GST_PADS) lines of a GStreamer log, as of a time.
+#[derive(Debug)]
+pub struct TopologyState {
+    pub format: TopologyFormat,
+    // Lines after this time are ignored.
+    pub at: Option<Duration>,
+    // Elements with their pads, in order of appearance.
+    pub elements: Vec<(String, Vec<String>)>,
+    // Links from a source pad to a sink pad, as (element, pad) pairs.
+    pub links: Vec<((String, String), (String, String))>,
+    // Time of the last line with time.
+    pub last_time: Option<Duration>,
+    pub add_pad_regex: Regex,
+    pub remove_pad_regex: Regex,
+    pub link_regex: Regex,
+    pub unlink_regex: Regex,
+}
+
+impl TopologyState {
+    pub fn new(format: TopologyFormat, at: Option<Duration>) -> TopologyState {
+        TopologyState {
+            format,
+            at,
+            elements: Vec::new(),
+            links: Vec::new(),
+            last_time: None,
+            add_pad_regex: Regex::new(r"^adding pad '([^']*)'").unwrap(),
+            remove_pad_regex: Regex::new(r"^removing pad '([^']*)'").unwrap(),
+            link_regex: Regex::new(r"^linked ([^\s:]+):(\S+) and ([^\s:]+):([^\s,]+)").unwrap(),
+            unlink_regex: Regex::new(r"^unlinked ([^\s:]+):(\S+) and ([^\s:]+):([^\s,]+)").unwrap(),
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.elements.clear();
+        self.links.clear();
+        self.last_time = None;
+    }
+
+    fn add_pad(&mut self, element: &str, pad: &str) {
+        let index = match self.elements.iter().position(|(name, _)| name == element) {
+            Some(index) => index,
+            None => {
+                self.elements.push((element.to_string(), Vec::new()));
+                self.elements.len() - 1
+            }
+        };
+        let pads = &mut self.elements[index].1;
+        if !pads.iter().any(|name| name == pad) {
+            pads.push(pad.to_string());
+        }
+    }
+
+    fn remove_pad(&mut self, element: &str, pad: &str) {
+        for (name, pads) in &mut self.elements {
+            if name == element {
+                pads.retain(|name| name != pad);
+            }
+        }
+        let is_pad = |(e, p): &(String, String)| e == element && p == pad;
+        self.links
+            .retain(|(source, sink)| !is_pad(source) && !is_pad(sink));
+    }
+
+    // Updates the topology with the line, if it's a GStreamer log line about pads or links.
+    pub fn process(&mut self, line: &[u8], time: Option<Duration>) {
+        if time.is_some() {
+            self.last_time = time;
+        }
+        if self
+            .at
+            .is_some_and(|at| self.last_time.is_some_and(|time| time > at))
+        {
+            return;
+        }
+        let Some(parsed) = GstLogLine::parse(line) else {
+            return;
+        };
+        let Some(message) = parsed.field(GstField::Message) else {
+            return;
+        };
+        let text = |captures: &regex::bytes::Captures, index: usize| {
+            String::from_utf8_lossy(&captures[index]).into_owned()
+        };
+        if let Some(captures) = self.add_pad_regex.captures(message)
+            && let Some(element) = parsed.field(GstField::Object)
+        {
+            self.add_pad(&String::from_utf8_lossy(element), &text(&captures, 1));
+        } else if let Some(captures) = self.remove_pad_regex.captures(message)
+            && let Some(element) = parsed.field(GstField::Object)
+        {
+            self.remove_pad(&String::from_utf8_lossy(element), &text(&captures, 1));
+        } else if let Some(captures) = self.link_regex.captures(message) {
+            let source = (text(&captures, 1), text(&captures, 2));
+            let sink = (text(&captures, 3), text(&captures, 4));
+            self.add_pad(&source.0, &source.1);
+            self.add_pad(&sink.0, &sink.1);
+            if !self.links.contains(&(source.clone(), sink.clone())) {
+                self.links.push((source, sink));
+            }
+        } else if let Some(captures) = self.unlink_regex.captures(message) {
+            let link = (
+                (text(&captures, 1), text(&captures, 2)),
+                (text(&captures, 3), text(&captures, 4)),
+            );
+            self.links.retain(|other| *other != link);
+        }
+    }
+
+    // Renders the topology, preceded by the filename of the input (if any) in a way that
+    // doesn't break the format (eg: a comment in Graphviz).
+    pub fn render(&self, filename: Option<&str>) -> String {
+        match (self.format, filename) {
+            (TopologyFormat::Dot, Some(filename)) => {
+                format!("// {}\n{}", filename, self.render_dot())
+            }
+            (TopologyFormat::Dot, None) => self.render_dot(),
+            (TopologyFormat::Tree, Some(filename)) => {
+                format!("{}:\n{}", filename, self.render_tree())
+            }
+            (TopologyFormat::Tree, None) => self.render_tree(),
+        }
+    }
+
+    fn render_dot(&self) -> String {
+        let quote = |text: &str| format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""));
+        let mut dot = String::from("digraph pipeline {\n  rankdir=LR;\n  node [shape=box];\n");
+        for (element, pads) in &self.elements {
+            dot += &format!("  subgraph {} {{\n", quote(&format!("cluster_{}", element)));
+            dot += &format!("    label={};\n", quote(element));
+            for pad in pads {
+                dot += &format!(
+                    "    {} [label={}];\n",
+                    quote(&format!("{}:{}", element, pad)),
+                    quote(pad)
+                );
+            }
+            dot += "  }\n";
+        }
+        for ((source_element, source_pad), (sink_element, sink_pad)) in &self.links {
+            dot += &format!(
+                "  {} -> {};\n",
+                quote(&format!("{}:{}", source_element, source_pad)),
+                quote(&format!("{}:{}", sink_element, sink_pad))
+            );
+        }
+        dot += "}";
+        dot
+    }
+
+    fn render_tree(&self) -> String {
+        let mut lines = Vec::new();
+        for (element, pads) in &self.elements {
+            lines.push(element.clone());
+            for pad in pads {
+                let mut line = format!("  {}", pad);
+                for (source, sink) in &self.links {
+                    if source.0 == *element && source.1 == *pad {
+                        line += &format!(" -> {}:{}", sink.0, sink.1);
+                    } else if sink.0 == *element && sink.1 == *pad {
+                        line += &format!(" <- {}:{}", source.0, source.1);
+                    }
+                }
+                lines.push(line);
+            }
+        }
+        lines.join("\n")
+    }
+}
+
+// Output formats of the state change timeline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StateTimelineFormat {
+    // One row per state change.
+    Table,
+    // One row per element, with its state along the time.
+    Gantt,
+}
+
+// How a state change ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StateChangeResult {
+    Success,
+    // Completed asynchronously (eg: a sink prerolling).
+    Async,
+    Failure,
+    // Started (asynchronously) but not completed before the end of the input.
+    Pending,
+}
+
+#[derive(Debug)]
+pub struct StateChange {
+    pub from: String,
+    pub to: String,
+    pub start: Option<Duration>,
+    pub end: Option<Duration>,
+    pub result: StateChangeResult,
+}
+
+// State changes of every element, extracted from the GST_STATES lines of a GStreamer log.
+#[derive(Debug)]
+pub struct StateTimelineState {
+    pub format: StateTimelineFormat,
+    // State changes of every element, in order of appearance of the elements.
+    pub elements: Vec<(String, Vec<StateChange>)>,
+    // State change in progress of every element: (from, to, start, async).
+    pub pending: HashMap<String, (String, String, Option<Duration>, bool)>,
+    pub start_regex: Regex,
+    pub commit_regex: Regex,
+    pub async_regex: Regex,
+    pub failure_regex: Regex,
+}
+
+impl StateTimelineState {
+    const GANTT_WIDTH: usize = 60;
+
+    pub fn new(format: StateTimelineFormat) -> StateTimelineState {
+        StateTimelineState {
+            format,
+            elements: Vec::new(),
+            pending: HashMap::new(),
+            start_regex: Regex::new(r"setting state from (\w+) to (\w+)").unwrap(),
+            commit_regex: Regex::new(r"^committing state from (\w+) to (\w+)").unwrap(),
+            async_regex: Regex::new(r"^element will change state ASYNC").unwrap(),
+            failure_regex: Regex::new(r"^have FAILURE change_state return").unwrap(),
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.elements.clear();
+        self.pending.clear();
+    }
+
+    fn push(&mut self, element: &str, change: StateChange) {
+        match self.elements.iter_mut().find(|(name, _)| name == element) {
+            Some((_, changes)) => changes.push(change),
+            None => self.elements.push((element.to_string(), vec![change])),
+        }
+    }
+
+    // Updates the timeline with the line, if it's a GStreamer log line about state changes.
+    pub fn process(&mut self, parsed: &GstLogLine, time: Option<Duration>) {
+        let (Some(element), Some(message)) = (
+            parsed.field(GstField::Object),
+            parsed.field(GstField::Message),
+        ) else {
+            return;
+        };
+        let element = String::from_utf8_lossy(element).into_owned();
+        let text = |captures: &regex::bytes::Captures, index: usize| {
+            String::from_utf8_lossy(&captures[index]).into_owned()
+        };
+        if let Some(captures) = self.start_regex.captures(message) {
+            self.pending.insert(
+                element,
+                (text(&captures, 1), text(&captures, 2), time, false),
+            );
+        } else if self.async_regex.is_match(message) {
+            if let Some(pending) = self.pending.get_mut(&element) {
+                pending.3 = true;
+            }
+        } else if let Some(captures) = self.commit_regex.captures(message) {
+            let (from, to) = (text(&captures, 1), text(&captures, 2));
+            let (start, is_async) = match self.pending.remove(&element) {
+                Some((pending_from, pending_to, start, is_async))
+                    if pending_from == from && pending_to == to =>
+                {
+                    (start, is_async)
+                }
+                _ => (time, false),
+            };
+            let result = if is_async {
+                StateChangeResult::Async
+            } else {
+                StateChangeResult::Success
+            };
+            self.push(
+                &element,
+                StateChange {
+                    from,
+                    to,
+                    start,
+                    end: time,
+                    result,
+                },
+            );
+        } else if self.failure_regex.is_match(message)
+            && let Some((from, to, start, _)) = self.pending.remove(&element)
+        {
+            let result = StateChangeResult::Failure;
+            self.push(
+                &element,
+                StateChange {
+                    from,
+                    to,
+                    start,
+                    end: time,
+                    result,
+                },
+            );
+        }
+    }
+
+    // Moves the asynchronous state changes which never completed to the timeline.
+    fn finish(&mut self) {
+        let mut pending: Vec<_> = self
+            .pending
+            .drain()
+            .filter(|(_, pending)| pending.3)
+            .collect();
+        pending.sort_by_key(|(_, pending)| pending.2);
+        for (element, (from, to, start, _)) in pending {
+            let result = StateChangeResult::Pending;
+            self.push(
+                &element,
+                StateChange {
+                    from,
+                    to,
+                    start,
+                    end: None,
+                    result,
+                },
+            );
+        }
+    }
+
+    // Renders the timeline, preceded by a heading with the filename of the input, if any.
+    pub fn render(&mut self, filename: Option<&str>) -> String {
+        self.finish();
+        let timeline = match self.format {
+            StateTimelineFormat::Table => self.render_table(),
+            StateTimelineFormat::Gantt => self.render_gantt(),
+        };
+        match filename {
+            Some(filename) => format!("{}:\n{}", filename, timeline),
+            None => timeline,
+        }
+    }
+
+    fn render_table(&self) -> String {
+        let time = |time: Option<Duration>| time.map_or("-".to_string(), format_seconds);
+        let width = self
+            .elements
+            .iter()
+            .map(|(name, _)| name.len())
+            .max()
+            .unwrap_or(0)
+            .max(7);
+        let mut lines = vec![format!(
+            "{:width$} {:22} {:>16} {:>16} {:>12} RESULT",
+            "ELEMENT", "CHANGE", "START", "END", "DURATION"
+        )];
+        for (element, changes) in &self.elements {
+            for change in changes {
+                let duration = match (change.start, change.end) {
+                    (Some(start), Some(end)) => format_seconds(end.saturating_sub(start)),
+                    _ => "-".to_string(),
+                };
+                lines.push(format!(
+                    "{:width$} {:22} {:>16} {:>16} {:>12} {}",
+                    element,
+                    format!("{}->{}", change.from, change.to),
+                    time(change.start),
+                    time(change.end),
+                    duration,
+                    format!("{:?}", change.result).to_uppercase()
+                ));
+            }
+        }
+        lines.join("\n")
+    }
+
+    fn render_gantt(&self) -> String {
+        let times = self
+            .elements
+            .iter()
+            .flat_map(|(_, changes)| changes.iter())
+            .flat_map(|change| [change.start, change.end])
+            .flatten();
+        let (Some(first), Some(last)) = (times.clone().min(), times.max()) else {
+            return "No state changes found".to_string();
+        };
+        let width = self
+            .elements
+            .iter()
+            .map(|(name, _)| name.len())
+            .max()
+            .unwrap_or(0);
+        let column_time = |column: usize| {
+            first + (last - first).mul_f64(column as f64 / (Self::GANTT_WIDTH - 1) as f64)
+        };
+        let symbol = |state: &str| match state {
+            "NULL" => '_',
+            "READY" => 'r',
+            "PAUSED" => 'p',
+            "PLAYING" => 'P',
+            _ => '?',
+        };
+        let mut lines = vec![format!(
+            "{:width$} |{:<half$}{:>half$}|",
+            "",
+            format_seconds(first),
+            format_seconds(last),
+            half = Self::GANTT_WIDTH / 2
+        )];
+        for (element, changes) in &self.elements {
+            let mut row = String::new();
+            for column in 0..Self::GANTT_WIDTH {
+                let time = column_time(column);
+                let mut cell = ' ';
+                for change in changes {
+                    let start = change.start.or(change.end).unwrap_or(first);
+                    let end = change.end.unwrap_or(last);
+                    if time < start {
+                        break;
+                    }
+                    cell = if time < end || change.result == StateChangeResult::Pending {
+                        '~'
+                    } else if change.result == StateChangeResult::Failure {
+                        // Only the first column after the failure shows it.
+                        if column == 0 || column_time(column - 1) < end {
+                            'X'
+                        } else {
+                            symbol(&change.from)
+                        }
+                    } else {
+                        symbol(&change.to)
+                    };
+                }
+                row.push(cell);
+            }
+            lines.push(format!("{:width$} |{}|", element, row));
+        }
+        lines.push("_ NULL  r READY  p PAUSED  P PLAYING  ~ changing  X failed".to_string());
+        lines.join("\n")
+    }
+}
+
+// Gaps found by the Gap command in the current input.
+#[derive(Debug, Default)]
+pub struct GapState {
+    // Time of the previous line having a timestamp.
+    pub previous_time: Option<Duration>,
+    // Biggest gap since the last printed line, to be signaled with a marker before the next one.
+    pub marker: Option<Duration>,
+    // Biggest gaps found, with the number of the line after them, from bigger to smaller.
+    pub biggest: Vec<(Duration, u64)>,
+}
+
+impl GapState {
+    const MAX_BIGGEST: usize = 10;
+
+    pub fn add(&mut self, gap: Duration, line_number: u64) {
+        self.marker = self.marker.max(Some(gap));
+        let position = self.biggest.partition_point(|(other, _)| *other >= gap);
+        if position < GapState::MAX_BIGGEST {
+            self.biggest.insert(position, (gap, line_number));
+            self.biggest.truncate(GapState::MAX_BIGGEST);
+        }
+    }
+
+    pub fn marker_line(gap: Duration, color: bool) -> String {
+        style_if(color, Colour::Yellow.bold())
+            .paint(format!("----- {:.3}s gap -----", gap.as_secs_f64()))
+            .to_string()
+    }
+
+    pub fn summary(&self, color: bool) -> String {
+        let mut summary = style_if(color, Colour::Yellow.bold())
+            .paint("----- Biggest gaps -----")
+            .to_string();
+        for (gap, line_number) in &self.biggest {
+            summary += &format!(
+                "\n{:>12} before line {}",
+                format!("{:.3}s", gap.as_secs_f64()),
+                line_number
+            );
+        }
+        summary
+    }
+}
+
+// Formats a duration as seconds with microsecond precision (eg: "1.234567").
+pub fn format_seconds(duration: Duration) -> String {
+    format!("{}.{:06}", duration.as_secs(), duration.subsec_micros())
+}
+
+// Times needed to resolve the relative time ranges of the current input.
+#[derive(Debug, Default)]
+pub struct TimeFilterState {
+    // First timestamp found in the input.
+    pub first_time: Option<Duration>,
+    // Last timestamp of the input, only known once its end has been reached.
+    pub last_time: Option<Duration>,
+}
+
+#[derive(Debug)]
+pub enum Command {
+    // Discards the line if no substring matches Filter, otherwise highlights the matched text.
+    // When there's a field, only that field of GStreamer log lines is taken into account.
+    Filter(
+        Regex,
+        Style,
+        /* negative */ bool,
+        /* highlight */ bool,
+        /* field */ Option<GstField>,
+    ),
+    // Highlights the matched text (if present). Doesn't discard the current line.
+    Highlight(Regex, Style, /* field */ Option<GstField>),
+    // Searches and replaces the matched text (if present). Doesn't discard the current line.
+    Substitution(Regex, String, /* field */ Option<GstField>),
+    // Filters lines that have a timestamp (see TimeParser) and are in the time range.
+    FilterTime(TimeRange),
+    // Assuming a GStreamer log format, locates the different thread ids and assigns a different
+    // style to each of them.
+    HighlightThreads,
+    // Adds a column with the time elapsed since the previous printed line (and optionally since
+    // the first one), highlighting it when it's longer than the threshold.
+    TimeDelta(
+        /* threshold */ Option<Duration>,
+        /* since_first */ bool,
+    ),
+    // Signals the places where the time between consecutive lines is longer than the threshold.
+    Gap(/* threshold */ Duration),
+    // Assuming a GStreamer log format, assigns a different style to each debug category.
+    HighlightCategories,
+    // Assuming a GStreamer log format, assigns a different style to each element referenced in
+    // the line, and a shade of it to each of its pads.
+    HighlightObjects,
+    // Assuming a GStreamer log format, discards the lines whose debug category (and level)
+    // isn't selected by the rules.
+    FilterCategory(Vec<CategoryRule>),
+    // Assuming a GStreamer log format, discards the lines whose debug level isn't allowed.
+    FilterLevel(/* allowed */ Vec<DebugLevel>),
+    // Checks that the buffer timestamps (PTS, DTS) of every object don't go backwards and that
+    // there are no holes nor overlaps longer than the threshold between consecutive buffers,
+    // appending the problems found to the line.
+    CheckTimestamps(/* threshold */ Duration),
+    // Appends the GstCaps and GstStructures found in the line to it, with a field in each line,
+    // marking what changed since the previous caps of the same object if diff is true.
+    PrettyCaps(/* diff */ bool),
+    // Discards the line if the boolean expression of regexes isn't true, otherwise highlights
+    // the text matched by each (non negated) regex.
+    Expression(Expression, Vec<(Regex, Style, /* negated */ bool)>),
+}
+
+#[derive(Debug)]
+pub enum CommandLineOption {
+    // -h, --help.
+    Help,
+    // -f FILE, --file FILE, or any argument after "--".
+    InputFile(String),
+    // -F FILE, --follow FILE.
+    FollowFile(String),
+    // -H, --with-filename.
+    WithFilename,
+}
+
+// Holds the context to process each line. Context would be a list of words to
+// match (with colors), things to memorize, or other kind of commands to be
+// done on lines. It should be like a list of commands to apply to lines.
+#[derive(Debug)]
+pub struct Context {
+    // Command line options other than line processing commands.
+    pub options: VecDeque<CommandLineOption>,
+    // Sequence of commands to apply to each line.
+    pub commands: VecDeque<Command>,
+    // Don't trim the lines, just remove the line break.
+    pub keep_whitespace: bool,
+    // How to handle the ANSI escape sequences in the input.
+    pub ansi_input: AnsiInput,
+    // Whether the output is colored (see ColorMode).
+    pub color: bool,
+    // Theme and color capabilities used to build the styles.
+    pub palette: Palette,
+    // Locates and parses the timestamps of the lines.
+    pub time_parser: TimeParser,
+    // Internal global states needed for some commands.
+    pub multiline_selection_state: MultilineSelectionState,
+    pub highlight_threads_state: HighlightIdsState,
+    pub highlight_categories_state: HighlightIdsState,
+    pub highlight_objects_state: HighlightObjectsState,
+    pub context_lines_state: ContextLinesState,
+    pub time_filter_state: TimeFilterState,
+    pub time_delta_state: TimeDeltaState,
+    pub gap_state: GapState,
+    pub timestamps_state: TimestampsState,
+    pub caps_state: CapsState,
+    // Only present when the pipeline topology is requested instead of the lines.
+    pub topology_state: Option<TopologyState>,
+    // Only present when the state change timeline is requested.
+    pub state_timeline_state: Option<StateTimelineState>,
+}
+
+impl Context {
+    pub fn new(args: Vec<String>) -> anyhow::Result<Self> {
+        let mut options: VecDeque<CommandLineOption> = VecDeque::new();
+        let mut commands: VecDeque<Command> = VecDeque::new();
+        let mut multiline_selection = LineSelection::Neutral;
+        let mut keep_whitespace = false;
+        let mut ansi_input = AnsiInput::Raw;
+        let mut color_mode = ColorMode::Auto;
+        let mut theme = Theme::Dark;
+        let mut stable_colors = false;
+        let mut topology_format = None;
+        let mut topology_at = None;
+        let mut state_timeline_state = None;
+        let mut context_lines_state = ContextLinesState::default();
+        let mut time_format = TimeFormat::Auto;
+        let mut command_args: Vec<String> = Vec::new();
+
+        // Options are processed first, because some of them affect how commands are parsed.
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            if arg.starts_with("-") {
+                if arg == OPTION_HELP || arg == OPTION_HELP_SHORT {
+                    options.push_back(CommandLineOption::Help);
+                    break; // Don't process any other option.
+                } else if arg == OPTION_FILE
+                    || arg == OPTION_FILE_SHORT
+                    || arg == OPTION_FOLLOW
+                    || arg == OPTION_FOLLOW_SHORT
+                {
+                    match args.next() {
+                        Some(file) if arg == OPTION_FOLLOW || arg == OPTION_FOLLOW_SHORT => {
+                            if file == STDIN_FILENAME {
+                                return Err(anyhow::anyhow!("Stdin can't be followed"));
+                            }
+                            options.push_back(CommandLineOption::FollowFile(file))
+                        }
+                        Some(file) => options.push_back(CommandLineOption::InputFile(file)),
+                        None => {
+                            return Err(anyhow::anyhow!(format!(
+                                "Option {:} requires a FILE argument. Use -h for help.",
+                                arg
+                            )));
+                        }
+                    }
+                } else if arg == OPTION_WITH_FILENAME || arg == OPTION_WITH_FILENAME_SHORT {
+                    options.push_back(CommandLineOption::WithFilename);
+                } else if arg == OPTION_KEEP_WHITESPACE || arg == OPTION_KEEP_WHITESPACE_SHORT {
+                    keep_whitespace = true;
+                } else if arg == OPTION_STRIP_ANSI {
+                    ansi_input = AnsiInput::Strip;
+                } else if arg == OPTION_KEEP_ANSI {
+                    ansi_input = AnsiInput::Keep;
+                } else if arg == OPTION_AFTER_CONTEXT
+                    || arg == OPTION_AFTER_CONTEXT_SHORT
+                    || arg == OPTION_BEFORE_CONTEXT
+                    || arg == OPTION_BEFORE_CONTEXT_SHORT
+                    || arg == OPTION_CONTEXT
+                    || arg == OPTION_CONTEXT_SHORT
+                {
+                    let lines = match args.next().map(|n| n.parse::<usize>()) {
+                        Some(Ok(lines)) => lines,
+                        _ => {
+                            return Err(anyhow::anyhow!(format!(
+                                "Option {:} requires a number of lines. Use -h for help.",
+                                arg
+                            )));
+                        }
+                    };
+                    if arg != OPTION_AFTER_CONTEXT && arg != OPTION_AFTER_CONTEXT_SHORT {
+                        context_lines_state.before = lines;
+                    }
+                    if arg != OPTION_BEFORE_CONTEXT && arg != OPTION_BEFORE_CONTEXT_SHORT {
+                        context_lines_state.after = lines;
+                    }
+                } else if let Some(mode) = arg.strip_prefix(OPTION_COLOR) {
+                    color_mode = match mode {
+                        "auto" => ColorMode::Auto,
+                        "always" => ColorMode::Always,
+                        "never" => ColorMode::Never,
+                        _ => {
+                            return Err(anyhow::anyhow!(format!(
+                                "Invalid color mode \"{:}\": it must be auto, always or never. Use -h for help.",
+                                mode
+                            )));
+                        }
+                    };
+                } else if arg == OPTION_TOPOLOGY || arg.starts_with(OPTION_TOPOLOGY_FORMAT) {
+                    topology_format = match arg.strip_prefix(OPTION_TOPOLOGY) {
+                        Some("") | Some("=dot") => Some(TopologyFormat::Dot),
+                        Some("=tree") => Some(TopologyFormat::Tree),
+                        _ => {
+                            return Err(anyhow::anyhow!(format!(
+                                "Invalid option: {:}. The topology format must be dot or tree. Use -h for help.",
+                                arg
+                            )));
+                        }
+                    };
+                } else if let Some(format) = arg.strip_prefix(OPTION_STATE_TIMELINE) {
+                    let format = match format {
+                        "" | "=table" => StateTimelineFormat::Table,
+                        "=gantt" => StateTimelineFormat::Gantt,
+                        _ => {
+                            return Err(anyhow::anyhow!(format!(
+                                "Invalid option: {:}. The state timeline format must be table or gantt. Use -h for help.",
+                                arg
+                            )));
+                        }
+                    };
+                    state_timeline_state = Some(StateTimelineState::new(format));
+                } else if let Some(time) = arg.strip_prefix(OPTION_TOPOLOGY_AT) {
+                    topology_at = Some(time.to_string());
+                } else if arg == OPTION_STABLE_COLORS {
+                    stable_colors = true;
+                } else if let Some(name) = arg.strip_prefix(OPTION_THEME) {
+                    theme = match Theme::from_name(name) {
+                        Some(theme) => theme,
+                        None => {
+                            return Err(anyhow::anyhow!(format!(
+                                "Invalid theme \"{:}\": it must be dark, light, high-contrast, colorblind or classic. Use -h for help.",
+                                name
+                            )));
+                        }
+                    };
+                } else if arg == OPTION_TIME_FORMAT || arg.starts_with(OPTION_TIME_FORMAT_VALUE) {
+                    // Like the rest of long options, the format goes after "=", but it can also be
+                    // the next argument.
+                    let format = match arg.strip_prefix(OPTION_TIME_FORMAT_VALUE) {
+                        Some(format) => Some(format.to_string()),
+                        None => args.next(),
+                    };
+                    time_format = match format.and_then(|f| TimeFormat::from_name(&f)) {
+                        Some(format) => format,
+                        None => {
+                            return Err(anyhow::anyhow!(format!(
+                                "Option {:} requires a format: auto, gst, syslog, logcat or iso8601. Use -h for help.",
+                                arg
+                            )));
+                        }
+                    };
+                } else if arg == OPTION_END_OF_COMMANDS {
+                    // Everything after "--" is an input file, even if it looks like a command.
+                    for file in args.by_ref() {
+                        options.push_back(CommandLineOption::InputFile(file));
+                    }
+                } else {
+                    return Err(anyhow::anyhow!(format!(
+                        "Invalid option: {:}. Use -h for help.",
+                        arg
+                    )));
+                }
+            } else {
+                command_args.push(arg);
+            }
+        }
+
+        let time_parser = TimeParser::new(time_format);
+        let topology_state = match (topology_format, topology_at) {
+            (Some(format), Some(at)) => match time_parser.parse_bound(&at) {
+                // Like the end of a time range, it includes all the times beyond its precision.
+                Some((_, time, precision)) => Some(TopologyState::new(
+                    format,
+                    Some(time + precision - Duration::from_nanos(1)),
+                )),
+                None => {
+                    return Err(anyhow::anyhow!(format!(
+                        "Invalid time \"{:}\" in option {:}TIME. Use -h for help.",
+                        at, OPTION_TOPOLOGY_AT
+                    )));
+                }
+            },
+            (Some(format), None) => Some(TopologyState::new(format, None)),
+            (None, Some(_)) => {
+                return Err(anyhow::anyhow!(format!(
+                    "Option {:}TIME requires {:}. Use -h for help.",
+                    OPTION_TOPOLOGY_AT, OPTION_TOPOLOGY
+                )));
+            }
+            (None, None) => None,
+        };
+        let palette = Palette::new(theme);
+        let mut styles = StyleIterator::new(palette, false, true, true);
+        let mut highlight_threads_state = HighlightIdsState::new(
+            StyleIterator::new(palette, true, true, false),
+            stable_colors,
+        );
+        let mut highlight_categories_state = HighlightIdsState::new(
+            StyleIterator::new(palette, false, true, true),
+            stable_colors,
+        );
+        let mut highlight_objects_state = HighlightObjectsState::new(
+            StyleIterator::new(palette, false, true, false),
+            stable_colors,
+            palette,
+        );
+        for mut arg in command_args {
+            // Some commands can have a pinned style instead of the next one (eg: "fc{red}:error").
+            let mut pinned_style = None;
+            if let Some((name, rest)) = arg.split_once('{')
+                && STYLED_COMMANDS.contains(&name.split('@').next().unwrap())
+                && let Some((style, rest)) = rest.split_once("}:")
+            {
+                pinned_style = Some(palette.parse_style(style)?);
+                arg = format!("{:}:{:}", name, rest);
+            }
+            // Some commands can be scoped to a field of GStreamer log lines (eg: "fc@object:src").
+            let mut field = None;
+            if let Some((name, rest)) = arg.split_once('@')
+                && FIELD_SCOPED_COMMANDS.contains(&name)
+                && let Some((field_name, rest)) = rest.split_once(':')
+            {
+                field = Some(GstField::from_name(field_name).ok_or_else(|| {
+                    anyhow::anyhow!(format!(
+                        "Unknown field \"{:}\" in command \"{:}@{:}:\". Valid fields: {:}",
+                        field_name,
+                        name,
+                        field_name,
+                        GstField::NAMES.join(", ")
+                    ))
+                })?);
+                arg = format!("{:}:{:}", name, rest);
+            }
+            if arg.starts_with(OPTION_FILTER_NO_HIGHLIGHT) {
+                arg = arg.drain(OPTION_FILTER_NO_HIGHLIGHT.len()..).collect();
+                let regex = RegexBuilder::new(&arg).case_insensitive(true).build();
+                if regex.is_err() {
+                    return Err(anyhow::anyhow!(format!("{:?}", regex.err().unwrap())));
                 }
                 commands.push_back(Command::Filter(
                     regex.unwrap(),
                     styles.next().unwrap(),
                     false,
                     false,
+                    field,
                 ));
             } else if arg.starts_with(OPTION_HIGHLIGHT) {
                 arg = arg.drain(OPTION_HIGHLIGHT.len()..).collect();
@@ -284,7 +3133,11 @@ impl Context {
                 if regex.is_err() {
                     return Err(anyhow::anyhow!(format!("{:?}", regex.err().unwrap())));
                 }
-                commands.push_back(Command::Highlight(regex.unwrap(), styles.next().unwrap()));
+                commands.push_back(Command::Highlight(
+                    regex.unwrap(),
+                    pinned_style.unwrap_or_else(|| styles.next().unwrap()),
+                    field,
+                ));
             } else if arg.starts_with(OPTION_NEGATIVE_FILTER) {
                 arg = arg.drain(OPTION_NEGATIVE_FILTER.len()..).collect();
                 let regex = RegexBuilder::new(&arg).case_insensitive(true).build();
@@ -296,6 +3149,7 @@ impl Context {
                     styles.next().unwrap(),
                     true,
                     false,
+                    field,
                 ));
             } else if arg.starts_with(OPTION_SUBSTITUTION) {
                 arg = arg.drain(OPTION_SUBSTITUTION.len()..).collect();
@@ -312,26 +3166,167 @@ impl Context {
                     return Err(anyhow::anyhow!(format!("{:?}", regex.err().unwrap())));
                 }
                 let replacement = tokens[1].to_string();
-                commands.push_back(Command::Substitution(regex.unwrap(), replacement));
+                commands.push_back(Command::Substitution(regex.unwrap(), replacement, field));
             } else if arg.starts_with(OPTION_FILTER_TIME) {
                 arg = arg.drain(OPTION_FILTER_TIME.len()..).collect();
-                let delimiter = "-".to_string();
-                let tokens: Vec<&str> = arg.split(&delimiter).collect();
-                if tokens.len() != 2 {
+                let range = if let Some(duration) = arg.strip_prefix('+') {
+                    parse_duration(duration).map(TimeRange::FromStart)
+                } else if let Some(duration) =
+                    arg.strip_prefix('-').and_then(|arg| arg.strip_suffix('$'))
+                {
+                    parse_duration(duration).map(TimeRange::ToEnd)
+                } else if let Some((begin, Some(duration))) = arg
+                    .rsplit_once('+')
+                    .map(|(begin, duration)| (begin, parse_duration(duration)))
+                {
+                    time_parser.parse_bound(begin).map(|(_, begin, _)| {
+                        TimeRange::Absolute(Some(begin), Some(begin + duration))
+                    })
+                } else {
+                    // The end includes all the times beyond its precision (eg: "-24.79" includes 24.7905).
+                    time_parser.parse_range(&arg).map(|(begin, end)| {
+                        TimeRange::Absolute(
+                            begin.map(|(time, _)| time),
+                            end.map(|(time, precision)| time + precision - Duration::from_nanos(1)),
+                        )
+                    })
+                };
+                let range = match range {
+                    Some(range) => range,
+                    None => {
+                        return Err(anyhow::anyhow!(
+                            "Filter time command \"ft:\" requires two valid times of the same format (even if they're empty), or a relative range. Examples: ft:0:00:05-0:00:06 ft:0:00:05- ft:-0:00:06 ft:24.78-24.79 \"ft:Oct 17 12:03:44-Oct 17 12:04\" ft:+5s ft:0:00:24.7+200ms ft:-30s$"
+                        ));
+                    }
+                };
+                if !matches!(range, TimeRange::Absolute(None, _)) {
+                    multiline_selection = LineSelection::ExplicitlyForbidden;
+                }
+                if matches!(range, TimeRange::ToEnd(_))
+                    && options
+                        .iter()
+                        .any(|o| matches!(o, CommandLineOption::FollowFile(_)))
+                {
                     return Err(anyhow::anyhow!(
-                        "Filter time command \"ft:\" requires two expressions (even if they're empty). Examples: ft:0:00:05-0:00:06 ft:0:00:05- ft:-0:00:06"
+                        "Time ranges relative to the end (ft:-DURATION$) can't be used when following a file"
                     ));
                 }
-                if !tokens[0].is_empty() {
-                    multiline_selection = LineSelection::ExplicitlyForbidden;
-                }
-                commands.push_back(Command::FilterTime(
-                    time_regex.clone().unwrap(),
-                    tokens[0].to_string(),
-                    tokens[1].to_string(),
-                ));
-            } else if arg.starts_with(OPTION_HIGHLIGHT_THREADS) {
+                commands.push_back(Command::FilterTime(range));
+            } else if let Some(mappings) = arg.strip_prefix(OPTION_HIGHLIGHT_THREADS) {
+                highlight_threads_state.pin_styles(mappings, &palette, OPTION_HIGHLIGHT_THREADS)?;
                 commands.push_back(Command::HighlightThreads);
+            } else if arg.starts_with(OPTION_EXPRESSION) {
+                arg = arg.drain(OPTION_EXPRESSION.len()..).collect();
+                let mut parser = ExpressionParser::new(&arg);
+                let expression = parser.parse()?;
+                let mut regexes = Vec::new();
+                for (pattern, negated) in parser.patterns {
+                    let regex = RegexBuilder::new(&pattern).case_insensitive(true).build();
+                    if regex.is_err() {
+                        return Err(anyhow::anyhow!(format!("{:?}", regex.err().unwrap())));
+                    }
+                    regexes.push((
+                        regex.unwrap(),
+                        pinned_style.unwrap_or_else(|| styles.next().unwrap()),
+                        negated,
+                    ));
+                }
+                commands.push_back(Command::Expression(expression, regexes));
+            } else if arg.starts_with(OPTION_TIME_DELTA) {
+                arg = arg.drain(OPTION_TIME_DELTA.len()..).collect();
+                let mut threshold = None;
+                let mut since_first = false;
+                for token in arg.split(',').filter(|token| !token.is_empty()) {
+                    if token == "first" {
+                        since_first = true;
+                    } else if let Some(duration) = parse_duration(token) {
+                        threshold = Some(duration);
+                    } else {
+                        return Err(anyhow::anyhow!(format!(
+                            "Invalid parameter \"{:}\" in time delta command \"dt:\". Examples: dt: dt:100ms dt:1s,first",
+                            token
+                        )));
+                    }
+                }
+                commands.push_back(Command::TimeDelta(threshold, since_first));
+            } else if let Some(mappings) = arg.strip_prefix(OPTION_HIGHLIGHT_OBJECTS) {
+                highlight_objects_state.elements.pin_styles(
+                    mappings,
+                    &palette,
+                    OPTION_HIGHLIGHT_OBJECTS,
+                )?;
+                commands.push_back(Command::HighlightObjects);
+            } else if let Some(mappings) = arg.strip_prefix(OPTION_HIGHLIGHT_CATEGORIES) {
+                highlight_categories_state.pin_styles(
+                    mappings,
+                    &palette,
+                    OPTION_HIGHLIGHT_CATEGORIES,
+                )?;
+                commands.push_back(Command::HighlightCategories);
+            } else if arg.starts_with(OPTION_FILTER_CATEGORY) {
+                arg = arg.drain(OPTION_FILTER_CATEGORY.len()..).collect();
+                let rules = arg
+                    .split(',')
+                    .map(CategoryRule::parse)
+                    .collect::<anyhow::Result<Vec<CategoryRule>>>()?;
+                commands.push_back(Command::FilterCategory(rules));
+            } else if arg.starts_with(OPTION_FILTER_LEVEL) {
+                arg = arg.drain(OPTION_FILTER_LEVEL.len()..).collect();
+                let (exact, names) = match arg.strip_prefix('=') {
+                    Some(names) => (true, names),
+                    None => (false, arg.as_str()),
+                };
+                let mut allowed = Vec::new();
+                for name in names.split(',') {
+                    let Some(level) = DebugLevel::from_name(name.as_bytes()) else {
+                        return Err(anyhow::anyhow!(format!(
+                            "Invalid level \"{:}\" in level filter command \"lvl:\". Examples: lvl:warning lvl:=DEBUG,LOG lvl:4",
+                            name
+                        )));
+                    };
+                    if exact {
+                        allowed.push(level);
+                    } else {
+                        allowed.extend(DebugLevel::ALL.iter().filter(|other| **other <= level));
+                    }
+                }
+                commands.push_back(Command::FilterLevel(allowed));
+            } else if let Some(text) = arg.strip_prefix(OPTION_CHECK_TIMESTAMPS) {
+                let threshold = if text.is_empty() {
+                    Some(Duration::from_millis(1))
+                } else {
+                    parse_duration(text)
+                };
+                match threshold {
+                    Some(threshold) => commands.push_back(Command::CheckTimestamps(threshold)),
+                    None => {
+                        return Err(anyhow::anyhow!(format!(
+                            "Invalid threshold \"{:}\" in buffer timestamps command \"pts:\". Examples: pts: pts:10ms",
+                            text
+                        )));
+                    }
+                }
+            } else if let Some(mode) = arg.strip_prefix(OPTION_PRETTY_CAPS) {
+                match mode {
+                    "" => commands.push_back(Command::PrettyCaps(false)),
+                    "diff" => commands.push_back(Command::PrettyCaps(true)),
+                    _ => {
+                        return Err(anyhow::anyhow!(format!(
+                            "Invalid mode \"{:}\" in pretty caps command \"pc:\". Examples: pc: pc:diff",
+                            mode
+                        )));
+                    }
+                }
+            } else if arg.starts_with(OPTION_GAP) {
+                arg = arg.drain(OPTION_GAP.len()..).collect();
+                match parse_duration(&arg) {
+                    Some(threshold) => commands.push_back(Command::Gap(threshold)),
+                    None => {
+                        return Err(anyhow::anyhow!(
+                            "Gap command \"gap:\" requires a duration. Examples: gap:200ms gap:1.5s"
+                        ));
+                    }
+                }
             } else {
                 // Filters can be specified with "fc:" (that's why we remove the header) or just with "" (that's why we're in an else)
                 if arg.starts_with(OPTION_FILTER) {
@@ -343,54 +3338,213 @@ impl Context {
                 }
                 commands.push_back(Command::Filter(
                     regex.unwrap(),
-                    styles.next().unwrap(),
+                    pinned_style.unwrap_or_else(|| styles.next().unwrap()),
                     false,
                     true,
+                    field,
                 ));
             }
         }
 
+        // A followed file never ends, so any input after it would never be read.
+        let inputs: Vec<&CommandLineOption> = options
+            .iter()
+            .filter(|o| {
+                matches!(
+                    o,
+                    CommandLineOption::InputFile(_) | CommandLineOption::FollowFile(_)
+                )
+            })
+            .collect();
+        if inputs
+            .iter()
+            .rev()
+            .skip(1)
+            .any(|o| matches!(o, CommandLineOption::FollowFile(_)))
+        {
+            return Err(anyhow::anyhow!(
+                "Only the last input file can be followed. Use -h for help."
+            ));
+        }
+        // The topology and the state timeline are printed at the end of the input, which never
+        // comes when following.
+        if (topology_state.is_some() || state_timeline_state.is_some())
+            && options
+                .iter()
+                .any(|o| matches!(o, CommandLineOption::FollowFile(_)))
+        {
+            return Err(anyhow::anyhow!(
+                "The pipeline topology and the state timeline can't be printed when following a file"
+            ));
+        }
+
         Ok(Context {
             options,
             commands,
+            keep_whitespace,
+            ansi_input,
+            color: color_mode.is_enabled(),
+            palette,
+            time_parser,
             multiline_selection_state: MultilineSelectionState {
                 multiline_selection,
-                forbid_next_line: false,
+                initial_selection: multiline_selection,
             },
-            highlight_threads_state: HighlightThreadsState::new(),
+            highlight_threads_state,
+            highlight_categories_state,
+            highlight_objects_state,
+            context_lines_state,
+            time_filter_state: TimeFilterState::default(),
+            time_delta_state: TimeDeltaState::default(),
+            gap_state: GapState::default(),
+            timestamps_state: TimestampsState::new(),
+            caps_state: CapsState::default(),
+            topology_state,
+            state_timeline_state,
         })
     }
 
+    // Longest duration of the time ranges relative to the end of the input, if any.
+    pub fn time_to_end(&self) -> Option<Duration> {
+        self.commands
+            .iter()
+            .filter_map(|command| match command {
+                Command::FilterTime(TimeRange::ToEnd(duration)) => Some(*duration),
+                _ => None,
+            })
+            .max()
+    }
+
     pub fn empty() -> Self {
         Context {
             options: VecDeque::new(),
             commands: VecDeque::new(),
+            keep_whitespace: false,
+            ansi_input: AnsiInput::Raw,
+            color: false,
+            palette: Palette::new(Theme::Dark),
+            time_parser: TimeParser::new(TimeFormat::Auto),
             multiline_selection_state: MultilineSelectionState {
                 multiline_selection: LineSelection::Neutral,
-                forbid_next_line: false,
+                initial_selection: LineSelection::Neutral,
             },
-            highlight_threads_state: HighlightThreadsState::new(),
+            highlight_threads_state: HighlightIdsState::new(
+                StyleIterator::new(Palette::new(Theme::Dark), true, true, false),
+                false,
+            ),
+            highlight_categories_state: HighlightIdsState::new(
+                StyleIterator::new(Palette::new(Theme::Dark), false, true, true),
+                false,
+            ),
+            highlight_objects_state: HighlightObjectsState::new(
+                StyleIterator::new(Palette::new(Theme::Dark), false, true, false),
+                false,
+                Palette::new(Theme::Dark),
+            ),
+            context_lines_state: ContextLinesState::default(),
+            time_filter_state: TimeFilterState::default(),
+            time_delta_state: TimeDeltaState::default(),
+            gap_state: GapState::default(),
+            timestamps_state: TimestampsState::new(),
+            caps_state: CapsState::default(),
+            topology_state: None,
+            state_timeline_state: None,
         }
     }
 }
 
-fn process_line(line: &str, context: &mut Context) {
+// Width of each of the time delta columns.
+const TIME_DELTA_WIDTH: usize = 12;
+
+// Prepends the time elapsed since the previous printed line (and since the first one) to
+// the output line. Lines without timestamp get an empty column, to keep the alignment.
+fn add_time_delta_column(
+    in_line: &[u8],
+    out_line: Vec<u8>,
+    threshold: Option<Duration>,
+    since_first: bool,
+    context: &mut Context,
+) -> Vec<u8> {
+    let state = &mut context.time_delta_state;
+    let mut column = match context.time_parser.parse_line(in_line) {
+        Some(time) => {
+            let first = *state.first_time.get_or_insert(time);
+            let delta = time.saturating_sub(state.previous_time.unwrap_or(time));
+            state.previous_time = Some(time);
+            let mut column = format!(
+                "{:>TIME_DELTA_WIDTH$}",
+                format!("+{}", format_seconds(delta))
+            );
+            if context.color && threshold.is_some_and(|threshold| delta > threshold) {
+                column = Colour::Red.bold().paint(column).to_string();
+            }
+            if since_first {
+                column = format!(
+                    "{:>TIME_DELTA_WIDTH$} {}",
+                    format_seconds(time - first),
+                    column
+                );
+            }
+            column
+        }
+        None => return add_blank_time_delta_column(out_line, since_first),
+    }
+    .into_bytes();
+    column.push(b' ');
+    column.extend(out_line);
+    column
+}
+
+// Adds an empty time delta column before the line, to keep it aligned with the rest (eg: for
+// lines without time, or context lines).
+fn add_blank_time_delta_column(line: Vec<u8>, since_first: bool) -> Vec<u8> {
+    let mut column = if since_first {
+        format!("{:TIME_DELTA_WIDTH$} {:TIME_DELTA_WIDTH$}", "", "")
+    } else {
+        format!("{:TIME_DELTA_WIDTH$}", "")
+    }
+    .into_bytes();
+    column.push(b' ');
+    column.extend(line);
+    column
+}
+
+// Applies the commands to the line and returns the text to print, if the line is selected.
+// Lines are handled as raw bytes, so invalid UTF-8 sequences are passed through untouched.
+fn process_line(line: &[u8], context: &mut Context) -> LineOutput {
     const DEBUG: bool = false;
 
-    let mut in_line: String = line.trim().to_string();
-    let mut out_line: String = in_line.clone();
+    let line = if context.keep_whitespace {
+        let line = line.strip_suffix(b"\n").unwrap_or(line);
+        line.strip_suffix(b"\r").unwrap_or(line)
+    } else {
+        line.trim_ascii()
+    };
+    // The commands work on the plain text of the line, recording the highlights to render them
+    // only once at the end.
+    let mut highlights = Highlights::default();
+    let mut in_line: Vec<u8> = match context.ansi_input {
+        AnsiInput::Raw => line.to_vec(),
+        AnsiInput::Strip => strip_ansi(line).0,
+        AnsiInput::Keep => highlights.keep_escapes(line),
+    };
 
     if DEBUG {
-        print!("--> {}", out_line);
+        println!("--> {}", String::from_utf8_lossy(&in_line));
     }
 
+    // The fields of GStreamer log lines are only found once, and again when a command changes
+    // the text.
+    let mut parsed = GstLogLine::parse(&in_line);
     let mut line_selection = LineSelection::Neutral;
+    let mut time_delta = None;
+    let mut gap = None;
     let mut commands_iter = context.commands.iter().peekable();
     while let Some(command) = commands_iter.next() {
         let optional_next_command = commands_iter.peek();
 
         match command {
-            Command::Filter(regex, style, negative, highlight) => {
+            Command::Filter(regex, style, negative, highlight, field) => {
                 if context.multiline_selection_state.multiline_selection
                     == LineSelection::ExplicitlyForbidden
                 {
@@ -398,18 +3552,18 @@ fn process_line(line: &str, context: &mut Context) {
                 }
                 #[allow(clippy::collapsible_else_if)]
                 if *negative {
-                    if regex.is_match(in_line.as_bytes()) {
+                    if is_match_in_field(&in_line, parsed.as_ref(), *field, regex) {
                         line_selection = LineSelection::ExplicitlyForbidden;
                     }
                 } else {
-                    if regex.is_match(in_line.as_bytes())
+                    if is_match_in_field(&in_line, parsed.as_ref(), *field, regex)
                         && line_selection != LineSelection::ExplicitlyForbidden
                     {
                         line_selection = LineSelection::ExplicitlyAllowed;
                     } else {
                         fn is_positive_filter(next_command: &Command) -> bool {
                             let result = match next_command {
-                                Command::Filter(_, _, negative, _) => !negative,
+                                Command::Filter(_, _, negative, _, _) => !negative,
                                 _ => false,
                             };
                             if DEBUG {
@@ -434,127 +3588,225 @@ fn process_line(line: &str, context: &mut Context) {
                         }
                     }
                 }
-                #[warn(clippy::collapsible_else_if)]
-                if *highlight {
-                    out_line = String::from_utf8(
-                        regex
-                            .replace_all(
-                                out_line.as_bytes(),
-                                style.paint("$0").to_string().as_bytes(),
-                            )
-                            .to_vec(),
-                    )
-                    .expect("Wrong UTF-8 conversion");
+                #[warn(clippy::collapsible_else_if)]
+                if *highlight {
+                    highlights.add_matches(&in_line, parsed.as_ref(), *field, regex, *style);
+                }
+            }
+            Command::Highlight(regex, style, field) => {
+                if context.multiline_selection_state.multiline_selection
+                    == LineSelection::ExplicitlyForbidden
+                {
+                    continue;
+                }
+                highlights.add_matches(&in_line, parsed.as_ref(), *field, regex, *style);
+            }
+            Command::Substitution(regex, replacement, field) => {
+                // Substitutions must be done for every line independently of multiline_selection,
+                // because, as they change stuff, they can influence on the FilterTime pattern matching.
+                let (line, edits) = replace_in_field(
+                    &in_line,
+                    parsed.as_ref(),
+                    *field,
+                    regex,
+                    replacement.as_bytes(),
+                );
+                if !edits.is_empty() {
+                    in_line = line;
+                    highlights.apply_edits(&edits);
+                    parsed = GstLogLine::parse(&in_line);
+                }
+            }
+            Command::FilterTime(range) => {
+                let time = match context.time_parser.parse_line(&in_line) {
+                    Some(time) => time,
+                    None => continue,
+                };
+                context.time_filter_state.first_time.get_or_insert(time);
+                let (begin, end) = match range.resolve(&context.time_filter_state) {
+                    Some(bounds) => bounds,
+                    None => continue,
+                };
+                if context.multiline_selection_state.multiline_selection
+                    != LineSelection::ExplicitlyAllowed
+                    && begin.is_some_and(|begin| time >= begin)
+                    && end.is_none_or(|end| time <= end)
+                {
+                    context.multiline_selection_state.multiline_selection =
+                        LineSelection::ExplicitlyAllowed;
+                }
+                if context.multiline_selection_state.multiline_selection
+                    != LineSelection::ExplicitlyForbidden
+                    && end.is_some_and(|end| time > end)
+                {
+                    context.multiline_selection_state.multiline_selection =
+                        LineSelection::ExplicitlyForbidden;
+                }
+            }
+            Command::HighlightThreads => {
+                if context.multiline_selection_state.multiline_selection
+                    == LineSelection::ExplicitlyForbidden
+                {
+                    continue;
+                }
+                // Lines which aren't complete GStreamer log lines (eg: without function) can still
+                // have the thread id as their third word.
+                let thread_id = match &parsed {
+                    Some(parsed) => parsed.field(GstField::Thread),
+                    None => in_line
+                        .split(u8::is_ascii_whitespace)
+                        .filter(|word| !word.is_empty())
+                        .nth(2),
+                };
+                if let Some(thread_id) = thread_id
+                    && thread_id.starts_with(b"0x")
+                {
+                    context
+                        .highlight_threads_state
+                        .highlight(thread_id, &in_line, &mut highlights);
                 }
             }
-            Command::Highlight(regex, style) => {
+            Command::HighlightObjects => {
                 if context.multiline_selection_state.multiline_selection
                     == LineSelection::ExplicitlyForbidden
                 {
                     continue;
                 }
-                out_line = String::from_utf8(
-                    regex
-                        .replace_all(
-                            out_line.as_bytes(),
-                            style.paint("$0").to_string().as_bytes(),
-                        )
-                        .to_vec(),
-                )
-                .expect("Wrong UTF-8 conversion");
+                context
+                    .highlight_objects_state
+                    .highlight(&in_line, &mut highlights);
             }
-            Command::Substitution(regex, replacement) => {
-                // Substitutions must be done for every line independently of multiline_selection,
-                // because, as they change stuff, they can influence on the FilterTime pattern matching.
-                in_line = String::from_utf8(
-                    regex
-                        .replace_all(in_line.as_bytes(), replacement.as_bytes())
-                        .to_vec(),
-                )
-                .expect("Wrong UTF-8 conversion");
-                out_line = String::from_utf8(
-                    regex
-                        .replace_all(out_line.as_bytes(), replacement.as_bytes())
-                        .to_vec(),
-                )
-                .expect("Wrong UTF-8 conversion");
+            Command::HighlightCategories => {
+                if context.multiline_selection_state.multiline_selection
+                    == LineSelection::ExplicitlyForbidden
+                {
+                    continue;
+                }
+                // Only the category field is highlighted, not other occurrences of its name (eg:
+                // in the file name).
+                if let Some(parsed) = &parsed
+                    && let Some(category) = parsed.field(GstField::Category)
+                    && let Some(range) = parsed.original_range(GstField::Category)
+                {
+                    let style = context.highlight_categories_state.style(category);
+                    highlights.add(range, style);
+                }
             }
-            Command::FilterTime(time_regex, begin, end) => {
-                if context.multiline_selection_state.forbid_next_line {
-                    context.multiline_selection_state.forbid_next_line = false;
-                    context.multiline_selection_state.multiline_selection =
-                        LineSelection::ExplicitlyForbidden;
-                } else {
-                    if !time_regex.is_match(in_line.to_string().as_bytes()) {
-                        continue;
-                    }
-                    if context.multiline_selection_state.multiline_selection
-                        != LineSelection::ExplicitlyAllowed
-                        && !begin.is_empty()
-                        && &in_line >= begin
-                        && (end.is_empty() || !end.is_empty() && &in_line[0..end.len()] <= end)
-                    {
-                        context.multiline_selection_state.multiline_selection =
-                            LineSelection::ExplicitlyAllowed;
+            Command::FilterCategory(rules) => {
+                if context.multiline_selection_state.multiline_selection
+                    == LineSelection::ExplicitlyForbidden
+                {
+                    continue;
+                }
+                // Like negative filters, this is an "a posteriori" filter: it can only reject.
+                let category = parsed
+                    .as_ref()
+                    .and_then(|parsed| parsed.field(GstField::Category));
+                let level = parsed.as_ref().and_then(GstLogLine::level);
+                let selected = match (category, level) {
+                    (Some(category), Some(level)) => {
+                        // Like in GST_DEBUG, the last matching rule is the one that applies.
+                        rules
+                            .iter()
+                            .rev()
+                            .find(|rule| rule.pattern.is_match(category))
+                            .is_some_and(|rule| rule.max_level.is_some_and(|max| level <= max))
                     }
-                    if context.multiline_selection_state.multiline_selection
-                        != LineSelection::ExplicitlyForbidden
-                        && !end.is_empty()
+                    _ => false,
+                };
+                if !selected {
+                    line_selection = LineSelection::ExplicitlyForbidden;
+                }
+            }
+            Command::TimeDelta(threshold, since_first) => {
+                // The column can only be added once the line is known to be printed.
+                time_delta = Some((*threshold, *since_first));
+            }
+            Command::Gap(threshold) => {
+                if let Some(time) = context.time_parser.parse_line(&in_line) {
+                    if let Some(previous_time) = context.gap_state.previous_time
+                        && time.saturating_sub(previous_time) > *threshold
                     {
-                        match (in_line[0..end.len()]).cmp(end) {
-                            Ordering::Equal => {
-                                // We want to print the last matched line if it still matches exactly
-                                // with the time, so we start forbidding on next line.
-                                context.multiline_selection_state.forbid_next_line = true;
-                            }
-                            Ordering::Greater => {
-                                // But if it has a later time, we already forbid this line.
-                                context.multiline_selection_state.multiline_selection =
-                                    LineSelection::ExplicitlyForbidden;
-                            }
-                            _ => {}
-                        }
+                        gap = Some(time - previous_time);
                     }
+                    context.gap_state.previous_time = Some(time);
                 }
             }
-            Command::HighlightThreads => {
+            Command::FilterLevel(allowed) => {
                 if context.multiline_selection_state.multiline_selection
                     == LineSelection::ExplicitlyForbidden
                 {
                     continue;
                 }
-                // Thread id is the 3rd field (using tab as separator) in GStreamer logs.
-                if let Some(thread_id) = in_line.split_whitespace().nth(2) {
-                    if !thread_id.starts_with("0x") {
-                        continue;
-                    }
-                    if !context.highlight_threads_state.ids.contains_key(thread_id) {
-                        context.highlight_threads_state.ids.insert(
-                            thread_id.to_string(),
-                            HighlightThreadsIdData {
-                                style: context
-                                    .highlight_threads_state
-                                    .styles
-                                    .next()
-                                    .unwrap()
-                                    .reverse(),
-                                regex: RegexBuilder::new(thread_id)
-                                    .case_insensitive(true)
-                                    .build()
-                                    .unwrap(),
-                            },
-                        );
+                // Like negative filters, this is an "a posteriori" filter: it can only reject.
+                let level = parsed.as_ref().and_then(GstLogLine::level);
+                if !level.is_some_and(|level| allowed.contains(&level)) {
+                    line_selection = LineSelection::ExplicitlyForbidden;
+                }
+            }
+            Command::CheckTimestamps(threshold) => {
+                if context.multiline_selection_state.multiline_selection
+                    == LineSelection::ExplicitlyForbidden
+                {
+                    continue;
+                }
+                let object = parsed
+                    .as_ref()
+                    .and_then(|parsed| parsed.field(GstField::Object))
+                    .unwrap_or_default();
+                let problems = context.timestamps_state.check(&in_line, object, *threshold);
+                if problems.is_empty() {
+                    continue;
+                }
+                let style = Colour::Red.bold();
+                for (_, range) in &problems {
+                    highlights.add(range.clone(), style);
+                }
+                let texts: Vec<&str> = problems.iter().map(|(text, _)| text.as_str()).collect();
+                let start = in_line.len() + 1;
+                in_line.extend_from_slice(format!(" [{}]", texts.join(", ")).as_bytes());
+                highlights.add(start..in_line.len(), style);
+                parsed = GstLogLine::parse(&in_line);
+            }
+            Command::PrettyCaps(diff) => {
+                if context.multiline_selection_state.multiline_selection
+                    == LineSelection::ExplicitlyForbidden
+                {
+                    continue;
+                }
+                let object = parsed
+                    .as_ref()
+                    .and_then(|parsed| parsed.field(GstField::Object))
+                    .unwrap_or_default()
+                    .to_vec();
+                let length = in_line.len();
+                context
+                    .caps_state
+                    .append(&mut in_line, &object, &mut highlights, *diff);
+                if in_line.len() != length {
+                    parsed = GstLogLine::parse(&in_line);
+                }
+            }
+            Command::Expression(expression, regexes) => {
+                if context.multiline_selection_state.multiline_selection
+                    == LineSelection::ExplicitlyForbidden
+                {
+                    continue;
+                }
+                let matches: Vec<bool> = regexes
+                    .iter()
+                    .map(|(regex, _, _)| regex.is_match(&in_line))
+                    .collect();
+                // Like negative filters, this is an "a posteriori" filter: it can only reject, so
+                // the result doesn't depend on its position among the other filters.
+                if !expression.evaluate(&matches) {
+                    line_selection = LineSelection::ExplicitlyForbidden;
+                    continue;
+                }
+                for (regex, style, negated) in regexes {
+                    if !negated {
+                        highlights.add_matches(&in_line, None, None, regex, *style);
                     }
-                    let data = context.highlight_threads_state.ids.get(thread_id).unwrap();
-                    out_line = String::from_utf8(
-                        data.regex
-                            .replace_all(
-                                out_line.as_bytes(),
-                                data.style.paint("$0").to_string().as_bytes(),
-                            )
-                            .to_vec(),
-                    )
-                    .expect("Wrong UTF-8 conversion");
                 }
             }
         }
@@ -562,42 +3814,321 @@ fn process_line(line: &str, context: &mut Context) {
             println!("   --> {:?} --> {:?}", command, line_selection);
         }
     }
-    if line_selection != LineSelection::ExplicitlyForbidden
-        && context.multiline_selection_state.multiline_selection
-            != LineSelection::ExplicitlyForbidden
+    let discarded =
+        context.multiline_selection_state.multiline_selection == LineSelection::ExplicitlyForbidden;
+    // Only the gaps inside the selected time ranges are signaled.
+    if let Some(gap) = gap
+        && !discarded
     {
-        if DEBUG {
-            println!("Result: {}", out_line);
-        } else {
-            println!("{}", out_line);
+        context
+            .gap_state
+            .add(gap, context.context_lines_state.line_number);
+    }
+    // Like the gaps, only the state changes inside the selected time ranges are considered.
+    if !discarded && let Some(state_timeline_state) = &mut context.state_timeline_state {
+        let time = context.time_parser.parse_line(&in_line);
+        if let Some(parsed) = &parsed {
+            state_timeline_state.process(parsed, time);
         }
     }
+    let result = if discarded {
+        LineOutput::Discarded
+    } else if line_selection == LineSelection::ExplicitlyForbidden {
+        // Context lines don't count for the time deltas, but they must stay aligned.
+        match time_delta {
+            Some((_, since_first)) => {
+                LineOutput::Context(add_blank_time_delta_column(in_line, since_first))
+            }
+            None => LineOutput::Context(in_line),
+        }
+    } else {
+        let mut out_line = highlights.render(&in_line, context.color);
+        if let Some((threshold, since_first)) = time_delta {
+            out_line = add_time_delta_column(&in_line, out_line, threshold, since_first, context);
+        }
+        LineOutput::Selected(out_line)
+    };
     if DEBUG {
+        if let LineOutput::Selected(out_line) = &result {
+            println!("Result: {}", String::from_utf8_lossy(out_line));
+        }
         println!("------");
     }
+    result
 }
 
-fn process_all(stdin: std::io::Stdin, mut context: Context) {
-    let mut line = String::new();
-    let mut exit = false;
-    while !exit {
-        line.clear();
-        match stdin.read_line(&mut line) {
-            Ok(n) => {
-                if n > 0 {
-                    process_line(&line, &mut context);
-                } else {
-                    exit = true;
+// Reads a file that can keep growing, waiting for more data when reaching its end,
+// instead of signaling an EOF. If the file is truncated, it's read again from the
+// start. If it's rotated (the path now points to a different file), the new file
+// is opened and read from the start.
+pub struct FollowReader {
+    path: std::path::PathBuf,
+    file: std::fs::File,
+    position: u64,
+}
+
+impl FollowReader {
+    pub fn open(path: &str) -> std::io::Result<FollowReader> {
+        Ok(FollowReader {
+            path: std::path::PathBuf::from(path),
+            file: std::fs::File::open(path)?,
+            position: 0,
+        })
+    }
+
+    #[cfg(unix)]
+    fn is_rotated(&self) -> bool {
+        use std::os::unix::fs::MetadataExt;
+        match (self.file.metadata(), std::fs::metadata(&self.path)) {
+            (Ok(current), Ok(new)) => current.dev() != new.dev() || current.ino() != new.ino(),
+            // The path may not exist for a while during the rotation.
+            _ => false,
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn is_rotated(&self) -> bool {
+        false
+    }
+}
+
+impl Read for FollowReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        loop {
+            let n = self.file.read(buf)?;
+            if n > 0 || buf.is_empty() {
+                self.position += n as u64;
+                return Ok(n);
+            }
+            if self.is_rotated() {
+                if let Ok(file) = std::fs::File::open(&self.path) {
+                    self.file = file;
+                    self.position = 0;
+                    continue;
                 }
+            } else if self.file.metadata()?.len() < self.position {
+                self.file.seek(std::io::SeekFrom::Start(0))?;
+                self.position = 0;
+                continue;
             }
-            Err(_) => {
-                exit = true;
+            std::thread::sleep(FOLLOW_POLL_INTERVAL);
+        }
+    }
+}
+
+// Sniffs the magic bytes at the start of the input and wraps it into the right
+// decompressor, if needed. Plain text inputs are returned as they are.
+fn open_input<R: BufRead + 'static>(mut input: R) -> std::io::Result<Box<dyn BufRead>> {
+    let header = input.fill_buf()?;
+    if header.starts_with(GZIP_MAGIC) {
+        Ok(Box::new(std::io::BufReader::new(
+            flate2::bufread::MultiGzDecoder::new(input),
+        )))
+    } else if header.starts_with(XZ_MAGIC) {
+        Ok(Box::new(std::io::BufReader::new(
+            xz2::bufread::XzDecoder::new_multi_decoder(input),
+        )))
+    } else if header.starts_with(ZSTD_MAGIC) {
+        Ok(Box::new(std::io::BufReader::new(
+            zstd::stream::read::Decoder::with_buffer(input)?,
+        )))
+    } else {
+        Ok(Box::new(input))
+    }
+}
+
+// Writes the processed lines to the output, adding the context lines (see
+// ContextLinesState) and the filename prefix, when needed.
+pub struct OutputWriter<W: Write> {
+    output: W,
+    // Like grep, selected lines have a ':' after the filename and context lines a '-'.
+    prefix: Option<String>,
+    context_prefix: Option<String>,
+    context_style: Style,
+}
+
+impl<W: Write> OutputWriter<W> {
+    pub fn new(output: W, filename: Option<&str>, color: bool) -> OutputWriter<W> {
+        let filename_style = style_if(color, Colour::Purple.normal());
+        OutputWriter {
+            output,
+            prefix: filename.map(|f| filename_style.paint(format!("{}:", f)).to_string()),
+            context_prefix: filename.map(|f| filename_style.paint(format!("{}-", f)).to_string()),
+            context_style: style_if(color, Style::new().dimmed()),
+        }
+    }
+
+    fn write_unprefixed(&mut self, text: &[u8]) -> std::io::Result<()> {
+        self.output.write_all(text)?;
+        self.output.write_all(b"\n")
+    }
+
+    fn write_line(&mut self, is_context: bool, line: &[u8]) -> std::io::Result<()> {
+        let prefix = if is_context {
+            &self.context_prefix
+        } else {
+            &self.prefix
+        };
+        if let Some(prefix) = prefix {
+            self.output.write_all(prefix.as_bytes())?;
+        }
+        if is_context {
+            self.output
+                .write_all(&paint_bytes(&self.context_style, line))?;
+        } else {
+            self.output.write_all(line)?;
+        }
+        self.output.write_all(b"\n")
+    }
+
+    // Writes the marker of the gap found since the last printed line, if any. Like the summary,
+    // it gets the filename prefix of the lines.
+    fn write_gap_marker(&mut self, gap_state: &mut GapState, color: bool) -> std::io::Result<()> {
+        if let Some(gap) = gap_state.marker.take() {
+            self.write_line(false, GapState::marker_line(gap, color).as_bytes())?;
+        }
+        Ok(())
+    }
+
+    // Processes a line and writes the result.
+    pub fn process(&mut self, line: &[u8], context: &mut Context) -> std::io::Result<()> {
+        context.context_lines_state.line_number += 1;
+        let output_line = process_line(line, context);
+        let state = &mut context.context_lines_state;
+        match output_line {
+            LineOutput::Selected(out_line) => {
+                let first_line_number = state.line_number - state.pending.len() as u64;
+                if state.is_enabled()
+                    && state.printed_any
+                    && state.last_printed_line_number != Some(first_line_number - 1)
+                {
+                    self.output.write_all(b"--\n")?;
+                }
+                for pending_line in state.pending.drain(..) {
+                    self.write_line(true, &pending_line)?;
+                }
+                self.write_gap_marker(&mut context.gap_state, context.color)?;
+                self.write_line(false, &out_line)?;
+                state.after_remaining = state.after;
+                state.last_printed_line_number = Some(state.line_number);
+                state.printed_any = true;
+            }
+            LineOutput::Context(plain_line) => {
+                if state.after_remaining > 0 {
+                    state.after_remaining -= 1;
+                    self.write_gap_marker(&mut context.gap_state, context.color)?;
+                    self.write_line(true, &plain_line)?;
+                    state.last_printed_line_number = Some(state.line_number);
+                } else if state.before > 0 {
+                    state.pending.push_back(plain_line);
+                    if state.pending.len() > state.before {
+                        state.pending.pop_front();
+                    }
+                }
             }
+            LineOutput::Discarded => {
+                state.pending.clear();
+                state.after_remaining = 0;
+            }
+        }
+        Ok(())
+    }
+}
+
+// Processes all the lines from input. When a filename is supplied, it's printed
+// before each output line, like "grep -H" does. Lines are read as raw bytes, so
+// invalid UTF-8 sequences don't stop the processing.
+fn process_all<R: BufRead>(
+    mut input: R,
+    context: &mut Context,
+    filename: Option<&str>,
+) -> std::io::Result<()> {
+    let mut writer = OutputWriter::new(std::io::stdout().lock(), filename, context.color);
+    let mut line: Vec<u8> = Vec::new();
+
+    // Context lines, time ranges and deltas, gaps and timestamp formats never span across
+    // different inputs.
+    context.multiline_selection_state.multiline_selection =
+        context.multiline_selection_state.initial_selection;
+    context.time_parser.reset();
+    let state = &mut context.context_lines_state;
+    state.pending.clear();
+    state.after_remaining = 0;
+    state.line_number = 0;
+    state.last_printed_line_number = None;
+    context.time_filter_state = TimeFilterState::default();
+    context.time_delta_state = TimeDeltaState::default();
+    context.gap_state = GapState::default();
+    context.timestamps_state.objects.clear();
+    context.caps_state.objects.clear();
+    if let Some(topology_state) = &mut context.topology_state {
+        topology_state.clear();
+    }
+    if let Some(state_timeline_state) = &mut context.state_timeline_state {
+        state_timeline_state.clear();
+    }
+
+    // When there are time ranges relative to the end, the lines which may be in them are
+    // kept (with their time, or the one of the previous line) until the end is reached.
+    let time_to_end = context.time_to_end();
+    let mut tail: VecDeque<(Option<Duration>, Vec<u8>)> = VecDeque::new();
+    let mut last_time: Option<Duration> = None;
+
+    loop {
+        line.clear();
+        if input.read_until(b'\n', &mut line)? == 0 {
+            break;
+        }
+        // The topology replaces the output of the lines.
+        if let Some(topology_state) = &mut context.topology_state {
+            let time = context.time_parser.parse_line(&strip_ansi(&line).0);
+            topology_state.process(&line, time);
+            continue;
+        }
+        let Some(time_to_end) = time_to_end else {
+            writer.process(&line, context)?;
+            continue;
+        };
+        let time = match context.ansi_input {
+            AnsiInput::Raw => context.time_parser.parse_line(&line),
+            _ => context.time_parser.parse_line(&strip_ansi(&line).0),
         };
+        if let Some(time) = time {
+            last_time = Some(last_time.map_or(time, |last| last.max(time)));
+        }
+        tail.push_back((last_time, line.clone()));
+        // Lines too old to be in the range anymore can be processed right away.
+        while let Some((time, _)) = tail.front()
+            && time.is_none_or(|time| time + time_to_end < last_time.unwrap())
+        {
+            let (_, old_line) = tail.pop_front().unwrap();
+            writer.process(&old_line, context)?;
+        }
     }
+
+    context.time_filter_state.last_time = last_time;
+    for (_, tail_line) in tail {
+        writer.process(&tail_line, context)?;
+    }
+
+    if !context.gap_state.biggest.is_empty() {
+        for line in context.gap_state.summary(context.color).lines() {
+            writer.write_line(false, line.as_bytes())?;
+        }
+    }
+    if let Some(topology_state) = &context.topology_state {
+        // The grep-style prefix would make the output invalid (eg: as a Graphviz graph).
+        writer.write_unprefixed(topology_state.render(filename).as_bytes())?;
+    }
+    if let Some(state_timeline_state) = &mut context.state_timeline_state {
+        // Like the topology, it's a block of its own, so the lines don't get the prefix.
+        writer.write_unprefixed(state_timeline_state.render(filename).as_bytes())?;
+    }
+    Ok(())
 }
+
 fn main() {
-    let context: Context = match Context::new(std::env::args().skip(1).collect()) {
+    let mut context: Context = match Context::new(std::env::args().skip(1).collect()) {
         Ok(c) => c,
         Err(e) => {
             eprintln!("Error: {:}", e);
@@ -606,6 +4137,8 @@ fn main() {
     };
 
     let mut exit = false;
+    let mut files: Vec<(String, /* follow */ bool)> = Vec::new();
+    let mut with_filename = false;
     for option in &context.options {
         match option {
             CommandLineOption::Help => {
@@ -613,12 +4146,772 @@ fn main() {
                 eprintln!(HELP_TEXT!(), binary_name = binary_name);
                 exit = true;
             }
+            CommandLineOption::InputFile(file) => files.push((file.clone(), false)),
+            CommandLineOption::FollowFile(file) => files.push((file.clone(), true)),
+            CommandLineOption::WithFilename => with_filename = true,
         }
     }
     if exit {
         std::process::exit(0);
     }
 
-    let stdin = std::io::stdin();
-    process_all(stdin, context);
+    if files.is_empty() {
+        files.push((STDIN_FILENAME.to_string(), false));
+    }
+    let mut status = 0;
+    for (file, follow) in &files {
+        let filename = if with_filename {
+            Some(if file == STDIN_FILENAME {
+                "(standard input)"
+            } else {
+                file.as_str()
+            })
+        } else {
+            None
+        };
+        let input = if file == STDIN_FILENAME {
+            open_input(std::io::stdin().lock())
+        } else if *follow {
+            FollowReader::open(file).and_then(|f| open_input(std::io::BufReader::new(f)))
+        } else {
+            std::fs::File::open(file).and_then(|f| open_input(std::io::BufReader::new(f)))
+        };
+        match input.and_then(|input| process_all(input, &mut context, filename)) {
+            Ok(()) => {}
+            // The output was closed (eg: piped to "head"), so there's nothing else to do.
+            Err(e) if e.kind() == std::io::ErrorKind::BrokenPipe => break,
+            Err(e) => {
+                eprintln!("Error: {:}: {:}", file, e);
+                status = 1;
+            }
+        }
+    }
+    std::process::exit(status);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Parses an expression, returning its tree (in Debug format) and its regex patterns.
+    fn parse_expression(text: &str) -> anyhow::Result<(String, Vec<(String, bool)>)> {
+        let mut parser = ExpressionParser::new(text);
+        let expression = parser.parse()?;
+        Ok((format!("{:?}", expression), parser.patterns))
+    }
+
+    fn patterns(patterns: &[(&str, bool)]) -> Vec<(String, bool)> {
+        patterns
+            .iter()
+            .map(|(pattern, negated)| (pattern.to_string(), *negated))
+            .collect()
+    }
+
+    #[test]
+    fn expression_precedence() {
+        let (tree, found) = parse_expression("a | b & c").unwrap();
+        assert_eq!(tree, "Or(Regex(0), And(Regex(1), Regex(2)))");
+        assert_eq!(found, patterns(&[("a", false), ("b", false), ("c", false)]));
+        let (tree, _) = parse_expression("a & b | c & d").unwrap();
+        assert_eq!(tree, "Or(And(Regex(0), Regex(1)), And(Regex(2), Regex(3)))");
+        let (tree, _) = parse_expression("a | b | c").unwrap();
+        assert_eq!(tree, "Or(Or(Regex(0), Regex(1)), Regex(2))");
+    }
+
+    #[test]
+    fn expression_not() {
+        let (tree, found) = parse_expression("!a & b").unwrap();
+        assert_eq!(tree, "And(Not(Regex(0)), Regex(1))");
+        assert_eq!(found, patterns(&[("a", true), ("b", false)]));
+        // A double negation isn't negated.
+        let (tree, found) = parse_expression("!!a").unwrap();
+        assert_eq!(tree, "Not(Not(Regex(0)))");
+        assert_eq!(found, patterns(&[("a", false)]));
+    }
+
+    #[test]
+    fn expression_grouping() {
+        let (tree, found) = parse_expression("(a | b) & !(c & d)").unwrap();
+        assert_eq!(
+            tree,
+            "And(Or(Regex(0), Regex(1)), Not(And(Regex(2), Regex(3))))"
+        );
+        assert_eq!(
+            found,
+            patterns(&[("a", false), ("b", false), ("c", true), ("d", true)])
+        );
+        let (tree, _) = parse_expression(" ( ( a ) ) ").unwrap();
+        assert_eq!(tree, "Regex(0)");
+    }
+
+    #[test]
+    fn expression_evaluate() {
+        let mut parser = ExpressionParser::new("(a | b) & !c");
+        let expression = parser.parse().unwrap();
+        assert!(expression.evaluate(&[true, false, false]));
+        assert!(expression.evaluate(&[false, true, false]));
+        assert!(!expression.evaluate(&[true, true, true]));
+        assert!(!expression.evaluate(&[false, false, false]));
+    }
+
+    #[test]
+    fn expression_quoting() {
+        let (tree, found) = parse_expression(r#""end of stream" & !"a|b (c)""#).unwrap();
+        assert_eq!(tree, "And(Regex(0), Not(Regex(1)))");
+        assert_eq!(
+            found,
+            patterns(&[("end of stream", false), ("a|b (c)", true)])
+        );
+        let (_, found) = parse_expression(r#""say \"hi\"" | x"#).unwrap();
+        assert_eq!(found, patterns(&[(r#"say "hi""#, false), ("x", false)]));
+        // Other escapes are kept for the regex.
+        let (_, found) = parse_expression(r#""a\.b""#).unwrap();
+        assert_eq!(found, patterns(&[(r"a\.b", false)]));
+    }
+
+    #[test]
+    fn expression_escapes() {
+        let (tree, found) = parse_expression(r"a\&b\ c | \(d\)").unwrap();
+        assert_eq!(tree, "Or(Regex(0), Regex(1))");
+        assert_eq!(found, patterns(&[(r"a\&b\ c", false), (r"\(d\)", false)]));
+    }
+
+    #[test]
+    fn expression_errors() {
+        let error = |text| parse_expression(text).unwrap_err().to_string();
+        assert_eq!(error(""), "Unexpected end of expression");
+        assert_eq!(error("a &"), "Unexpected end of expression");
+        assert_eq!(error("!"), "Unexpected end of expression");
+        assert_eq!(error("(a | b"), "Missing \")\" in expression");
+        assert_eq!(error("a b"), "Unexpected \"b\" in expression");
+        assert_eq!(error("a)"), "Unexpected \")\" in expression");
+        assert_eq!(error(r#""abc"#), "Missing closing quote in expression");
+        assert_eq!(error(r#""" | a"#), "Empty regex in expression");
+        assert_eq!(error("a | & b"), "Empty regex in expression");
+    }
+
+    // Renders the text with the style, like Highlights::render() does.
+    fn paint(style: Style, text: &str) -> String {
+        format!("{}{}{}", style.prefix(), text, style.suffix())
+    }
+
+    fn render(highlights: &Highlights, text: &[u8]) -> String {
+        String::from_utf8(highlights.render(text, true)).unwrap()
+    }
+
+    // Applies a substitution like the Substitution command does.
+    fn substitute(
+        highlights: &mut Highlights,
+        line: &[u8],
+        regex: &str,
+        replacement: &str,
+    ) -> Vec<u8> {
+        let regex = Regex::new(regex).unwrap();
+        let (line, edits) = replace_in_field(line, None, None, &regex, replacement.as_bytes());
+        highlights.apply_edits(&edits);
+        line
+    }
+
+    #[test]
+    fn highlights_growing_substitution() {
+        let (red, blue) = (Colour::Red.normal(), Colour::Blue.normal());
+        let mut highlights = Highlights::default();
+        highlights.add(2..4, red);
+        highlights.add(5..6, blue);
+        let line = substitute(&mut highlights, b"a bb c", "bb", "XXXX");
+        assert_eq!(line, b"a XXXX c");
+        assert_eq!(
+            render(&highlights, &line),
+            format!("a {} {}", paint(red, "XXXX"), paint(blue, "c"))
+        );
+    }
+
+    #[test]
+    fn highlights_shrinking_substitution() {
+        let (red, blue) = (Colour::Red.normal(), Colour::Blue.normal());
+        let mut highlights = Highlights::default();
+        highlights.add(0..1, blue);
+        highlights.add(2..4, red);
+        highlights.add(5..6, blue);
+        let line = substitute(&mut highlights, b"a bb c bb d", "bb", "Y");
+        assert_eq!(line, b"a Y c Y d");
+        assert_eq!(
+            render(&highlights, &line),
+            format!(
+                "{} {} {} Y d",
+                paint(blue, "a"),
+                paint(red, "Y"),
+                paint(blue, "c")
+            )
+        );
+    }
+
+    #[test]
+    fn highlights_empty_substitution() {
+        let (red, blue) = (Colour::Red.normal(), Colour::Blue.normal());
+        let mut highlights = Highlights::default();
+        highlights.add(2..4, red);
+        highlights.add(5..6, blue);
+        let line = substitute(&mut highlights, b"a bb c", "bb", "");
+        assert_eq!(line, b"a  c");
+        assert_eq!(
+            render(&highlights, &line),
+            format!("a  {}", paint(blue, "c"))
+        );
+
+        // Text inserted by an empty match is left out of the range starting there.
+        let mut highlights = Highlights::default();
+        highlights.add(0..3, red);
+        let line = substitute(&mut highlights, b"abc", "^", ">");
+        assert_eq!(line, b">abc");
+        assert_eq!(
+            render(&highlights, &line),
+            format!(">{}", paint(red, "abc"))
+        );
+    }
+
+    #[test]
+    fn highlights_partially_replaced_range() {
+        let red = Colour::Red.normal();
+        // The range is extended to cover the whole replacement.
+        let mut highlights = Highlights::default();
+        highlights.add(3..7, red);
+        let line = substitute(&mut highlights, b"hello world", "world", "there");
+        assert_eq!(
+            render(&highlights, &line),
+            format!("hel{}", paint(red, "lo there"))
+        );
+        let mut highlights = Highlights::default();
+        highlights.add(3..7, red);
+        let line = substitute(&mut highlights, b"hello world", "hello", "hi");
+        assert_eq!(
+            render(&highlights, &line),
+            format!("{}orld", paint(red, "hi w"))
+        );
+    }
+
+    #[test]
+    fn highlights_overlapping_and_nested() {
+        let (red, blue) = (Colour::Red.normal(), Colour::Blue.normal());
+        // The range added last wins.
+        let mut highlights = Highlights::default();
+        highlights.add(0..4, red);
+        highlights.add(2..6, blue);
+        assert_eq!(
+            render(&highlights, b"abcdef"),
+            format!("{}{}", paint(red, "ab"), paint(blue, "cdef"))
+        );
+        let mut highlights = Highlights::default();
+        highlights.add(0..6, red);
+        highlights.add(2..4, blue);
+        assert_eq!(
+            render(&highlights, b"abcdef"),
+            format!(
+                "{}{}{}",
+                paint(red, "ab"),
+                paint(blue, "cd"),
+                paint(red, "ef")
+            )
+        );
+        // A nested range added first is hidden.
+        let mut highlights = Highlights::default();
+        highlights.add(2..4, blue);
+        highlights.add(0..6, red);
+        assert_eq!(render(&highlights, b"abcdef"), paint(red, "abcdef"));
+        // Without color, nothing is rendered.
+        assert_eq!(highlights.render(b"abcdef", false), b"abcdef");
+    }
+
+    #[test]
+    fn highlights_kept_escapes() {
+        let red = Colour::Red.normal();
+        let mut highlights = Highlights::default();
+        let text = highlights.keep_escapes(b"\x1b[32mabcd\x1b[0m");
+        assert_eq!(text, b"abcd");
+        assert_eq!(highlights.render(&text, false), b"\x1b[32mabcd\x1b[0m");
+        // The input color is restored after the highlighted range.
+        highlights.add(1..2, red);
+        assert_eq!(
+            render(&highlights, &text),
+            format!("\x1b[32ma{}\x1b[32mcd\x1b[0m", paint(red, "b"))
+        );
+
+        // A reset inside the highlighted range ends the input color.
+        let mut highlights = Highlights::default();
+        let text = highlights.keep_escapes(b"\x1b[32mab\x1b[0mcd");
+        highlights.add(1..3, red);
+        assert_eq!(
+            render(&highlights, &text),
+            format!("\x1b[32ma{}d", paint(red, "bc"))
+        );
+
+        // The escapes follow the substitutions.
+        let mut highlights = Highlights::default();
+        let text = highlights.keep_escapes(b"\x1b[1mab\x1b[0m cd");
+        let line = substitute(&mut highlights, &text, "ab", "XYZ");
+        assert_eq!(highlights.render(&line, false), b"\x1b[1mXYZ\x1b[0m cd");
+        let line = substitute(&mut highlights, &line, "XYZ", "");
+        assert_eq!(highlights.render(&line, false), b"\x1b[1m\x1b[0m cd");
+    }
+
+    fn nanos(nanos: u64) -> Duration {
+        Duration::from_nanos(nanos)
+    }
+
+    #[test]
+    fn time_parsing() {
+        assert_eq!(
+            parse_time("0:01:10.881123150"),
+            Some((nanos(70_881_123_150), nanos(1)))
+        );
+        assert_eq!(
+            parse_time("1:10.5"),
+            Some((nanos(70_500_000_000), nanos(100_000_000)))
+        );
+        assert_eq!(
+            parse_time("0:00:05"),
+            Some((nanos(5_000_000_000), nanos(1_000_000_000)))
+        );
+        assert_eq!(
+            parse_time("70.88"),
+            Some((nanos(70_880_000_000), nanos(10_000_000)))
+        );
+        assert_eq!(
+            parse_time("70881ms"),
+            Some((nanos(70_881_000_000), nanos(1_000_000)))
+        );
+        assert_eq!(
+            parse_time("1.5m"),
+            Some((nanos(90_000_000_000), nanos(6_000_000_000)))
+        );
+        assert_eq!(parse_time("250ns"), Some((nanos(250), nanos(1))));
+        assert_eq!(
+            parse_time("2h"),
+            Some((nanos(7_200_000_000_000), nanos(3_600_000_000_000)))
+        );
+        // Digits beyond the nanosecond are ignored.
+        assert_eq!(
+            parse_time("0.1234567891"),
+            Some((nanos(123_456_789), nanos(1)))
+        );
+        for invalid in [
+            "",
+            "abc",
+            "1:2:3:4",
+            "0:01:10ms",
+            "1.2.3",
+            "-1",
+            "1e3",
+            ".5",
+        ] {
+            assert_eq!(parse_time(invalid), None, "{}", invalid);
+        }
+        assert_eq!(
+            parse_time("5."),
+            Some((nanos(5_000_000_000), nanos(1_000_000_000)))
+        );
+    }
+
+    #[test]
+    fn duration_parsing() {
+        assert_eq!(parse_duration("200ms"), Some(nanos(200_000_000)));
+        assert_eq!(parse_duration("1.5s"), Some(nanos(1_500_000_000)));
+        assert_eq!(parse_duration("2h"), Some(nanos(7_200_000_000_000)));
+        // Without unit, it would be a time.
+        assert_eq!(parse_duration("200"), None);
+        assert_eq!(parse_duration("0:00:01"), None);
+        assert_eq!(parse_duration("ms"), None);
+    }
+
+    #[test]
+    fn time_range_parsing() {
+        let parser = TimeParser::new(TimeFormat::Auto);
+        let second = nanos(1_000_000_000);
+        assert_eq!(
+            parser.parse_range("0:00:05-0:00:06"),
+            Some((Some((5 * second, second)), Some((6 * second, second))))
+        );
+        assert_eq!(
+            parser.parse_range("5-"),
+            Some((Some((5 * second, second)), None))
+        );
+        assert_eq!(
+            parser.parse_range("-24.79"),
+            Some((None, Some((nanos(24_790_000_000), nanos(10_000_000)))))
+        );
+        assert_eq!(parser.parse_range("-"), Some((None, None)));
+        assert_eq!(parser.parse_range("5"), None);
+        assert_eq!(parser.parse_range("a-b"), None);
+
+        // Oct 17 is the day 289 (counting from 0) of a non leap year.
+        let oct_17 = 289 * 86400;
+        let range = parser.parse_range("Oct 17 12:03:44-Oct 17 12:05").unwrap();
+        assert_eq!(range.0, Some(((oct_17 + 43424) * second, second)));
+        assert_eq!(range.1, Some(((oct_17 + 43500) * second, 60 * second)));
+        // The dashes of the dates aren't mistaken for the range delimiter.
+        let range = parser
+            .parse_range("10-17 12:03:44.123-10-17 12:04")
+            .unwrap();
+        assert_eq!(
+            range.0,
+            Some((
+                (oct_17 + 43424) * second + nanos(123_000_000),
+                nanos(1_000_000)
+            ))
+        );
+        assert_eq!(range.1, Some(((oct_17 + 43440) * second, 60 * second)));
+        let range = parser
+            .parse_range("2025-10-17T12:03:44+02:00-2025-10-17T10:04:00Z")
+            .unwrap();
+        let begin = range.0.unwrap().0;
+        assert_eq!(range.1.unwrap().0 - begin, 16 * second);
+        // Both bounds must have the same format.
+        assert_eq!(parser.parse_range("5s-Oct 17 12:05"), None);
+    }
+
+    #[test]
+    fn time_line_parsing() {
+        let second = nanos(1_000_000_000);
+        let mut parser = TimeParser::new(TimeFormat::Auto);
+        assert_eq!(parser.parse_line(b"no time here"), None);
+        assert_eq!(
+            parser.parse_line(b"  0:00:24.787450146   739  0x1ee2320 DEBUG"),
+            Some(nanos(24_787_450_146))
+        );
+        // The format detected from the first line is kept.
+        assert_eq!(parser.format, TimeFormat::GStreamer);
+        assert_eq!(
+            parser.parse_line(b"Oct 17 12:03:44 host app: message"),
+            None
+        );
+
+        let mut parser = TimeParser::new(TimeFormat::Iso8601);
+        assert_eq!(
+            parser.parse_line(br#"{"time": "1970-01-02T00:00:01.5Z", "msg": "hi"}"#),
+            Some(86401 * second + nanos(500_000_000))
+        );
+    }
+
+    #[test]
+    fn time_format_reset() {
+        let mut parser = TimeParser::new(TimeFormat::Auto);
+        assert!(
+            parser
+                .parse_line(b"Oct 17 12:03:44 host app: message")
+                .is_some()
+        );
+        assert_eq!(parser.format, TimeFormat::Syslog);
+        assert_eq!(parser.parse_line(b"0:00:01.000000000 1 0x1 DEBUG"), None);
+        // The next input detects its own format.
+        parser.reset();
+        assert_eq!(
+            parser.parse_line(b"0:00:01.000000000 1 0x1 DEBUG"),
+            Some(nanos(1_000_000_000))
+        );
+        assert_eq!(parser.format, TimeFormat::GStreamer);
+
+        // A forced format is kept.
+        let mut parser = TimeParser::new(TimeFormat::Syslog);
+        parser.reset();
+        assert_eq!(parser.format, TimeFormat::Syslog);
+    }
+
+    #[test]
+    fn iso8601_detection() {
+        let second = nanos(1_000_000_000);
+        // A date in a line without time doesn't lock the format.
+        let mut parser = TimeParser::new(TimeFormat::Auto);
+        assert_eq!(parser.parse_line(b"note 1970-01-01T00:00:05Z start"), None);
+        assert_eq!(parser.format, TimeFormat::Auto);
+        assert_eq!(
+            parser.parse_line(b"0:00:01.000000000 1 0x1 DEBUG"),
+            Some(second)
+        );
+        assert_eq!(parser.format, TimeFormat::GStreamer);
+
+        // Dates in the middle of several lines are detected.
+        let mut parser = TimeParser::new(TimeFormat::Auto);
+        assert_eq!(
+            parser.parse_line(br#"{"time": "1970-01-01T00:00:01Z"}"#),
+            None
+        );
+        assert_eq!(
+            parser.parse_line(br#"{"time": "1970-01-01T00:00:02Z"}"#),
+            None
+        );
+        assert_eq!(
+            parser.parse_line(br#"{"time": "1970-01-01T00:00:03Z"}"#),
+            Some(3 * second)
+        );
+        assert_eq!(parser.format, TimeFormat::Iso8601);
+        assert_eq!(
+            parser.parse_line(br#"{"time": "1970-01-01T00:00:04Z"}"#),
+            Some(4 * second)
+        );
+
+        // Dates at the start of the line are detected right away.
+        let mut parser = TimeParser::new(TimeFormat::Auto);
+        assert_eq!(
+            parser.parse_line(b"1970-01-01T00:00:01Z message"),
+            Some(second)
+        );
+        assert_eq!(parser.format, TimeFormat::Iso8601);
+    }
+
+    #[test]
+    fn gst_log_line_parsing() {
+        let line = b"0:00:01.234567890  1234 0x55d5c7a0 DEBUG  qtdemux qtdemux.c:12:push:<qtdemux0:audio_0> a message";
+        let parsed = GstLogLine::parse(line).unwrap();
+        let field = |field| std::str::from_utf8(parsed.field(field).unwrap()).unwrap();
+        assert_eq!(field(GstField::Timestamp), "0:00:01.234567890");
+        assert_eq!(field(GstField::Pid), "1234");
+        assert_eq!(field(GstField::Thread), "0x55d5c7a0");
+        assert_eq!(field(GstField::Level), "DEBUG");
+        assert_eq!(field(GstField::Category), "qtdemux");
+        assert_eq!(field(GstField::File), "qtdemux.c");
+        assert_eq!(field(GstField::Line), "12");
+        assert_eq!(field(GstField::Function), "push");
+        assert_eq!(field(GstField::Object), "qtdemux0:audio_0");
+        assert_eq!(field(GstField::Message), "a message");
+        assert_eq!(parsed.level(), Some(DebugLevel::Debug));
+        assert_eq!(parsed.original_range(GstField::Category), Some(42..49));
+
+        // Without object, and with a C++ function.
+        let line = b"0:00:01.2 1 0x1 WARN cat file.cpp:3:Foo::bar: text: with colons";
+        let parsed = GstLogLine::parse(line).unwrap();
+        assert_eq!(parsed.field(GstField::Function), Some(&b"Foo::bar"[..]));
+        assert_eq!(parsed.field(GstField::Object), None);
+        assert_eq!(
+            parsed.field(GstField::Message),
+            Some(&b"text: with colons"[..])
+        );
+        assert_eq!(parsed.level(), Some(DebugLevel::Warning));
+
+        // Empty message.
+        let parsed = GstLogLine::parse(b"0:00:01.2 1 0x1 INFO cat file.c:3:f:<obj>").unwrap();
+        assert_eq!(parsed.field(GstField::Object), Some(&b"obj"[..]));
+        assert_eq!(parsed.field(GstField::Message), Some(&b""[..]));
+
+        for invalid in [
+            &b"not a GStreamer log line"[..],
+            b"0:00:01.2 1 0x1 INFO cat",
+            b"abc 1 0x1 INFO cat file.c:3:f: message",
+            b"0:00:01.2 x 0x1 INFO cat file.c:3:f: message",
+            b"0:00:01.2 1 0x1 LOUD cat file.c:3:f: message",
+            b"0:00:01.2 1 0x1 INFO cat file.c:x:f: message",
+        ] {
+            assert!(GstLogLine::parse(invalid).is_none(), "{:?}", invalid);
+        }
+    }
+
+    #[test]
+    fn gst_log_line_parsing_with_colors() {
+        // Like GST_DEBUG_COLOR_MODE=on.
+        let line = b"0:00:01.2 \x1b[35m 1\x1b[00m 0x1 \x1b[32mINFO \x1b[00m \x1b[1;34mcat \x1b[00m file.c:3:f:<\x1b[00mobj\x1b[00m> hi";
+        let parsed = GstLogLine::parse(line).unwrap();
+        assert_eq!(parsed.field(GstField::Level), Some(&b"INFO"[..]));
+        assert_eq!(parsed.field(GstField::Category), Some(&b"cat"[..]));
+        assert_eq!(parsed.field(GstField::Object), Some(&b"obj"[..]));
+        assert_eq!(parsed.field(GstField::Message), Some(&b"hi"[..]));
+        // The ranges in the original line leave the escape sequences out.
+        let range = parsed.original_range(GstField::Category).unwrap();
+        assert_eq!(&line[range], b"cat");
+        let range = parsed.original_range(GstField::Object).unwrap();
+        assert_eq!(&line[range], b"obj");
+    }
+
+    #[test]
+    fn clock_time_parsing() {
+        assert_eq!(
+            TimestampsState::parse_clock_time(b"0:00:01.500000000"),
+            Some(nanos(1_500_000_000))
+        );
+        assert_eq!(
+            TimestampsState::parse_clock_time(b"99:99:99.999999999"),
+            None
+        );
+        assert_eq!(TimestampsState::parse_clock_time(b"none"), None);
+    }
+
+    #[test]
+    fn timestamps_holes_and_overlaps() {
+        let mut state = TimestampsState::new();
+        let threshold = nanos(1_000_000);
+        let mut check = |line: &str| -> Vec<String> {
+            state
+                .check(line.as_bytes(), b"dec:sink", threshold)
+                .into_iter()
+                .map(|(text, _)| text)
+                .collect()
+        };
+        assert!(check("pts 0:00:01.000000000, dur 0:00:00.040000000").is_empty());
+        assert!(check("pts 0:00:01.040000000, duration 0:00:00.040000000").is_empty());
+        assert_eq!(
+            check("pts 0:00:01.180000000, dur 0:00:00.040000000"),
+            ["hole of 0.100000s"]
+        );
+        assert_eq!(
+            check("PTS: 0:00:01.200000000, dur 0:00:00.040000000"),
+            ["overlap of 0.020000s"]
+        );
+        // Differences below the threshold are fine.
+        assert!(check("pts=(guint64)0:00:01.240500000, dur 0:00:00.040000000").is_empty());
+        // Without duration, the next pts can't be checked.
+        assert!(check("pts 0:00:01.280500000").is_empty());
+        assert!(check("pts 0:00:01.500000000").is_empty());
+        // Lines without timestamps are ignored.
+        assert!(check("dur 0:00:00.040000000").is_empty());
+        assert!(check("nothing to see").is_empty());
+    }
+
+    #[test]
+    fn timestamps_going_backwards() {
+        let mut state = TimestampsState::new();
+        let threshold = nanos(1_000_000);
+        let line = "pts 0:00:01.000000000, dts 99:99:99.999999999, dur 0:00:00.040000000";
+        assert!(state.check(line.as_bytes(), b"a", threshold).is_empty());
+        // Without a DTS (none), the PTS can't go back.
+        let line = "pts 0:00:00.900000000, dts 99:99:99.999999999, dur 0:00:00.040000000";
+        let problems = state.check(line.as_bytes(), b"a", threshold);
+        assert_eq!(problems.len(), 1);
+        assert_eq!(problems[0].0, "pts went back 0.100000s");
+        // The range is the one of the value.
+        assert_eq!(&line[problems[0].1.clone()], "0:00:00.900000000");
+
+        // With a DTS, the PTS can be reordered (eg: B-frames), but the DTS can't go back.
+        let mut state = TimestampsState::new();
+        let line = "pts 0:00:02.000000000 dts 0:00:01.900000000 dur 0:00:00.040000000";
+        assert!(state.check(line.as_bytes(), b"b", threshold).is_empty());
+        let line = "pts 0:00:01.960000000 dts 0:00:01.940000000 dur 0:00:00.040000000";
+        assert!(state.check(line.as_bytes(), b"b", threshold).is_empty());
+        let line = "pts 0:00:02.000000000 dts 0:00:01.880000000 dur 0:00:00.040000000";
+        let problems = state.check(line.as_bytes(), b"b", threshold);
+        assert_eq!(problems.len(), 1);
+        assert_eq!(problems[0].0, "dts went back 0.060000s");
+        assert_eq!(&line[problems[0].1.clone()], "0:00:01.880000000");
+        // Every object is tracked separately.
+        let line = "pts 0:00:00.000000000 dts 0:00:00.000000000";
+        assert!(state.check(line.as_bytes(), b"c", threshold).is_empty());
+    }
+
+    // Fields of a structure as strings, to compare them easily.
+    fn caps_fields(structure: &CapsStructure) -> Vec<(&str, &str)> {
+        structure
+            .fields
+            .iter()
+            .map(|(name, value)| {
+                (
+                    std::str::from_utf8(name).unwrap(),
+                    std::str::from_utf8(value).unwrap(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn caps_parsing() {
+        let text =
+            b"video/x-h264, stream-format=(string)avc, width=(int)1920, framerate=(fraction)30/1";
+        let (caps, length) = CapsStructure::parse_caps(text).unwrap();
+        assert_eq!(length, text.len());
+        assert_eq!(caps.len(), 1);
+        assert_eq!(caps[0].name, b"video/x-h264");
+        assert_eq!(
+            caps_fields(&caps[0]),
+            [
+                ("stream-format", "(string)avc"),
+                ("width", "(int)1920"),
+                ("framerate", "(fraction)30/1")
+            ]
+        );
+
+        // Caps features and several structures, one of them without fields.
+        let text = b"video/x-raw(memory:GLMemory), format=(string)RGBA; video/x-raw; audio/x-raw, rate=(int)48000; and more";
+        let (caps, length) = CapsStructure::parse_caps(text).unwrap();
+        assert_eq!(&text[..length], b"video/x-raw(memory:GLMemory), format=(string)RGBA; video/x-raw; audio/x-raw, rate=(int)48000");
+        assert_eq!(caps.len(), 3);
+        assert_eq!(caps[0].name, b"video/x-raw(memory:GLMemory)");
+        assert_eq!(caps[1].name, b"video/x-raw");
+        assert!(caps[1].fields.is_empty());
+        assert_eq!(caps_fields(&caps[2]), [("rate", "(int)48000")]);
+
+        // The first field must have a type.
+        assert!(CapsStructure::parse_caps(b"done, result=ok").is_none());
+        assert!(CapsStructure::parse_caps(b"video/x-raw").is_none());
+    }
+
+    #[test]
+    fn caps_values_with_quotes_and_brackets() {
+        let text = br#"s, list=(string){ avc, avc3 }, range=(int)[ 1, 2147483647 ], array=(int)< 1, 2 >, text=(string)"a, b; c", escaped=(string)"say \"hi, there\"", nested=(GstStructure)"t\,\ a\=\(int\)1\;", last=(int)1 rest of the line"#;
+        let (caps, _) = CapsStructure::parse_caps(text).unwrap();
+        assert_eq!(
+            caps_fields(&caps[0]),
+            [
+                ("list", "(string){ avc, avc3 }"),
+                ("range", "(int)[ 1, 2147483647 ]"),
+                ("array", "(int)< 1, 2 >"),
+                ("text", r#"(string)"a, b; c""#),
+                ("escaped", r#"(string)"say \"hi, there\"""#),
+                ("nested", r#"(GstStructure)"t\,\ a\=\(int\)1\;""#),
+                ("last", "(int)1"),
+            ]
+        );
+    }
+
+    #[test]
+    fn caps_finding() {
+        let line = b"<dec:sink> caps = video/x-raw, width=(int)1 (filter: audio/x-raw, rate=(int)2), result: done, x=1";
+        let found = CapsStructure::find_all(line);
+        assert_eq!(found.len(), 2);
+        assert_eq!(found[0][0].name, b"video/x-raw");
+        assert_eq!(found[1][0].name, b"audio/x-raw");
+        assert_eq!(caps_fields(&found[1][0]), [("rate", "(int)2")]);
+        assert!(CapsStructure::find_all(b"no caps, here=at all").is_empty());
+    }
+
+    #[test]
+    fn caps_pretty_printing() {
+        let mut state = CapsState::default();
+        let mut highlights = Highlights::default();
+        let mut line = b"caps = s, a=(int)1, b=(string)x".to_vec();
+        state.append(&mut line, b"pad", &mut highlights, false);
+        assert_eq!(
+            String::from_utf8(line).unwrap(),
+            "caps = s, a=(int)1, b=(string)x\n    s\n        a: (int)1\n        b: (string)x"
+        );
+        // Without diff, the caps aren't kept.
+        assert!(state.objects.is_empty());
+        let mut line = b"nothing here".to_vec();
+        state.append(&mut line, b"pad", &mut highlights, false);
+        assert_eq!(line, b"nothing here");
+    }
+
+    #[test]
+    fn caps_diff() {
+        let mut state = CapsState::default();
+        let mut highlights = Highlights::default();
+        let mut line = b"s, a=(int)1, b=(string)x, c=(int)3; t, d=(int)4".to_vec();
+        state.append(&mut line, b"pad", &mut highlights, true);
+        // The first caps of the object have nothing to compare with.
+        assert!(!line.contains(&b'~'));
+
+        let mut line = b"s, a=(int)1, b=(string)y, e=(int)5".to_vec();
+        state.append(&mut line, b"pad", &mut highlights, true);
+        assert_eq!(
+            String::from_utf8(line).unwrap(),
+            "s, a=(int)1, b=(string)y, e=(int)5\n    s\n        a: (int)1\n      ~ b: (string)x -> (string)y\n      + e: (int)5\n      - c: (int)3\n    - t"
+        );
+
+        let mut line = b"u, a=(int)1; t, d=(int)4".to_vec();
+        state.append(&mut line, b"pad", &mut highlights, true);
+        assert_eq!(
+            String::from_utf8(line).unwrap(),
+            "u, a=(int)1; t, d=(int)4\n    ~ s -> u\n        a: (int)1\n      - b: (string)y\n      - e: (int)5\n    + t\n      + d: (int)4"
+        );
+
+        // Other objects are compared with their own caps.
+        let mut line = b"s, a=(int)2".to_vec();
+        state.append(&mut line, b"other", &mut highlights, true);
+        assert!(!line.contains(&b'~'));
+    }
 }